@@ -0,0 +1,181 @@
+@group(0)
+@binding(0)
+var<storage, read> particles: array<Particle>;
+
+@group(0)
+@binding(1)
+var<storage, read_write> output: array<Particle>;
+
+@group(1)
+@binding(0)
+var<storage, read_write> allocator: Allocator;
+
+@group(1)
+@binding(1)
+var<storage, read_write> free_list: array<u32>;
+
+@group(1)
+@binding(2)
+var<storage, read_write> spawn_queue: array<Particle>;
+
+@group(2)
+@binding(0)
+var<storage, read_write> indirect: IndirectArgs;
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    radius: f32,
+    mass: f32,
+    // Low order part of `position`, only used with extended precision
+    position_lo: vec2<f32>,
+    level: u32,
+    time: u32,
+    color: u32,
+    // Index when uploaded, spawned particles count up from there and a shattered particle keeps
+    // it as its first fragment. The top bit marks selected particles and the one below
+    // the particles of the followed cluster
+    id: u32,
+}
+
+struct Allocator {
+    capacity: u32,
+    // Slots in use, dead particles below `count` are on the free list
+    count: atomic<u32>,
+    alive: atomic<u32>,
+    free_count: atomic<u32>,
+    compacted: atomic<u32>,
+    // Particles waiting in `spawn_queue`
+    spawn_count: atomic<u32>,
+    // The id given to the next spawned particle
+    next_id: u32,
+}
+
+struct IndirectArgs {
+    dispatch_x: u32,
+    dispatch_y: u32,
+    dispatch_z: u32,
+
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+
+    active_dispatch_x: u32,
+    active_dispatch_y: u32,
+    active_dispatch_z: u32,
+}
+
+const ID_MASK: u32 = 0x3fffffffu;
+const PARTICLES_PER_WORKGROUP: u32 = 256;
+const SPAWN_WORKGROUPS: u32 = 64;
+
+fn write_indirect(count: u32) {
+    indirect.dispatch_x = (count + PARTICLES_PER_WORKGROUP - 1u) / PARTICLES_PER_WORKGROUP;
+    indirect.dispatch_y = 1u;
+    indirect.dispatch_z = 1u;
+
+    indirect.vertex_count = 3u;
+    indirect.instance_count = count;
+    indirect.first_vertex = 0u;
+    indirect.first_instance = 0u;
+}
+
+// Moves the queued particles into the slots of dead particles first and appends the rest,
+// particles that don't fit are dropped. Every one gets a new id, so fragments aren't mistaken
+// for the particle they broke off from.
+@compute
+@workgroup_size(256)
+fn spawn(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let spawn_count = atomicLoad(&allocator.spawn_count);
+    let free_count = atomicLoad(&allocator.free_count);
+    let count = atomicLoad(&allocator.count);
+
+    for (var i = global_id.x; i < spawn_count; i += SPAWN_WORKGROUPS * PARTICLES_PER_WORKGROUP) {
+        var slot: u32;
+        if i < free_count {
+            slot = free_list[free_count - 1u - i];
+        } else {
+            slot = count + i - free_count;
+        }
+
+        if slot < allocator.capacity {
+            var particle = spawn_queue[i];
+            particle.id = (particle.id & ~ID_MASK) | ((allocator.next_id + i) & ID_MASK);
+            output[slot] = particle;
+        }
+    }
+}
+
+@compute
+@workgroup_size(1)
+fn finish_spawn() {
+    let spawn_count = atomicLoad(&allocator.spawn_count);
+    let free_count = atomicLoad(&allocator.free_count);
+    let reused = min(spawn_count, free_count);
+
+    let count = min(atomicLoad(&allocator.count) + spawn_count - reused, allocator.capacity);
+    atomicStore(&allocator.count, count);
+    atomicStore(&allocator.free_count, free_count - reused);
+    atomicStore(&allocator.spawn_count, 0u);
+    allocator.next_id = (allocator.next_id + spawn_count) & ID_MASK;
+
+    write_indirect(count);
+}
+
+// Puts the slots of dead particles on the free list at the start of a step, before
+// the particles emitted from the CPU and the fragments are spawned into them
+@compute
+@workgroup_size(256)
+fn collect_free(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= atomicLoad(&allocator.count) || particles[index].mass != 0.0 {
+        return;
+    }
+
+    let free_index = atomicAdd(&allocator.free_count, 1u);
+    free_list[free_index] = index;
+}
+
+// Runs once at the start of every frame, the spawn count is kept for the particles
+// emitted from the CPU before the frame
+@compute
+@workgroup_size(1)
+fn prepare() {
+    let count = min(atomicLoad(&allocator.count), allocator.capacity);
+    atomicStore(&allocator.count, count);
+    atomicStore(&allocator.alive, 0u);
+    atomicStore(&allocator.free_count, 0u);
+    atomicStore(&allocator.compacted, 0u);
+
+    write_indirect(count);
+}
+
+// Moves every living particle to the front of `output`
+@compute
+@workgroup_size(256)
+fn compact(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= atomicLoad(&allocator.count) {
+        return;
+    }
+
+    let particle = particles[index];
+    if particle.mass == 0.0 {
+        return;
+    }
+
+    let slot = atomicAdd(&allocator.compacted, 1u);
+    output[slot] = particle;
+}
+
+@compute
+@workgroup_size(1)
+fn finish_compaction() {
+    let count = atomicLoad(&allocator.compacted);
+    atomicStore(&allocator.count, count);
+    atomicStore(&allocator.alive, count);
+    atomicStore(&allocator.free_count, 0u);
+
+    write_indirect(count);
+}
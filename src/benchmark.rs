@@ -0,0 +1,284 @@
+use std::time::Instant;
+
+use anyhow::Context;
+use glam::{DVec2, Vec2};
+use log::info;
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    follow::{FollowModule, FollowTarget, InfoOutput},
+    particle::{Particle, ParticleSet, ID_MASK},
+    physics::Allocator,
+};
+
+/// Particle counts the follow statistics are timed at
+const FOLLOW_PARTICLES: [u32; 6] = [1 << 10, 1 << 12, 1 << 14, 1 << 16, 1 << 18, 1 << 20];
+const ITERATIONS: u32 = 20;
+/// The id of the followed particle
+const PARTICLE_ID: u32 = 3;
+const SELECTED: u32 = 1 << 31;
+const CLUSTER: u32 = 1 << 30;
+
+/// Times the follow statistics including their readback on a headless device
+/// and checks them against the same statistics computed on the CPU
+pub fn follow() -> anyhow::Result<()> {
+    let (device, queue) = tokio::runtime::Runtime::new()?.block_on(headless_device())?;
+    let capacity = *FOLLOW_PARTICLES.iter().max().unwrap();
+    let particles = random_particles(capacity as usize);
+
+    let particle_buffers = [0, 1].map(|_| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Benchmark Particle Buffer"),
+            contents: bytemuck::cast_slice(&particles),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    });
+    let allocator_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Benchmark Allocator Buffer"),
+        size: std::mem::size_of::<Allocator>() as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut follow_module = FollowModule::new(&device, &particle_buffers, &allocator_buffer);
+    follow_module.enabled = true;
+    follow_module.target = Some(FollowTarget::Particle { id: PARTICLE_ID });
+    follow_module.auto_zoom = true;
+    // Measures the percentile radius around the origin, the particle isn't found yet
+    follow_module.update_params(&queue);
+
+    println!(
+        "{:>10} {:>12} {:>16} {:>12} {:>16}",
+        "particles", "time (ms)", "per particle (ns)", "max error", "percentile error"
+    );
+    for count in FOLLOW_PARTICLES {
+        let allocator = Allocator {
+            capacity,
+            count,
+            ..Default::default()
+        };
+        queue.write_buffer(&allocator_buffer, 0, bytemuck::bytes_of(&allocator));
+
+        // Warms up, then runs every pass in a single submission so the readback latency
+        // doesn't hide the cost of the reduction
+        read_statistics(&device, &queue, &mut follow_module, 1)?;
+        let start = Instant::now();
+        let output = read_statistics(&device, &queue, &mut follow_module, ITERATIONS)?;
+        let elapsed = start.elapsed();
+
+        let time = elapsed / ITERATIONS;
+        let particles = &particles[..count as usize];
+        let error = max_error(&output, &cpu_statistics(particles));
+        let radius = percentile_radius(particles, follow_module.zoom_percentile);
+        let percentile_error = (output.percentile_radius - radius).abs() / radius;
+        println!(
+            "{count:>10} {:>12.3} {:>16.3} {error:>12.3e} {percentile_error:>16.3e}",
+            time.as_secs_f64() * 1e3,
+            time.as_secs_f64() * 1e9 / count as f64,
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the follow passes `iterations` times and reads back the last output
+fn read_statistics(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    follow_module: &mut FollowModule,
+    iterations: u32,
+) -> anyhow::Result<InfoOutput> {
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    for _ in 0..iterations {
+        follow_module.begin_pass(&mut encoder, 0);
+    }
+    follow_module.copy_buffer_to_buffer(&mut encoder, 0);
+    queue.submit(Some(encoder.finish()));
+    follow_module.map_readback();
+
+    follow_module
+        .wait_data(device)
+        .map(|(_, output)| output)
+        .context("Failed to read the follow statistics back from the GPU")
+}
+
+pub async fn headless_device() -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await
+        .context("Failed to find an appropriate adapter")?;
+    info!("Benchmarking on {}", adapter.get_info().name);
+
+    Ok(adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::default(),
+            },
+            None,
+        )
+        .await?)
+}
+
+/// Particles spread over a square away from the origin, one in eight is dead and has to be
+/// skipped, one in five is selected and one in three is in the flagged cluster
+fn random_particles(count: usize) -> ParticleSet {
+    let mut rng = rand::thread_rng();
+    (0..count)
+        .map(|i| {
+            let position = DVec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)) * 500.0;
+            let velocity = Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let mass = if i % 8 == 7 {
+                0.0
+            } else {
+                rng.gen_range(0.01..1.0)
+            };
+            let mut particle = Particle::new(position + 100.0, velocity, 0.1, mass);
+            particle.id = i as u32
+                | if i % 5 == 0 { SELECTED } else { 0 }
+                | if i % 3 == 0 { CLUSTER } else { 0 };
+            particle
+        })
+        .collect()
+}
+
+/// The statistics of `follow.wgsl` summed in double precision
+fn cpu_statistics(particles: &[Particle]) -> InfoOutput {
+    let alive = particles.iter().filter(|particle| particle.mass != 0.0);
+    let mut sum_position = DVec2::ZERO;
+    let mut sum_velocity = DVec2::ZERO;
+    let mut sum_moment = DVec2::ZERO;
+    let mut sum_momentum = DVec2::ZERO;
+    let mut sum_mass = 0.0;
+    let mut kinetic_energy = 0.0;
+    let mut angular_momentum = 0.0;
+    let mut specific_angular_momentum = 0.0;
+    let mut max_speed = 0.0f32;
+    let mut selection_moment = DVec2::ZERO;
+    let mut selection_mass = 0.0;
+    let mut particle_position = Vec2::ZERO;
+    let mut particle_mass = 0.0;
+    let mut heaviest_position = Vec2::ZERO;
+    let mut heaviest_mass = 0.0;
+    let mut cluster_moment = DVec2::ZERO;
+    let mut cluster_mass = 0.0;
+    let mut min_position = Vec2::MAX;
+    let mut max_position = Vec2::MIN;
+    let mut count = 0;
+    for particle in alive {
+        let position = particle.position + particle.position_lo;
+        let mass = particle.mass as f64;
+        sum_position += position.as_dvec2();
+        sum_velocity += particle.velocity.as_dvec2();
+        sum_moment += position.as_dvec2() * mass;
+        sum_momentum += particle.velocity.as_dvec2() * mass;
+        sum_mass += mass;
+        kinetic_energy += 0.5 * mass * particle.velocity.as_dvec2().length_squared();
+        let specific = position.as_dvec2().perp_dot(particle.velocity.as_dvec2());
+        angular_momentum += mass * specific;
+        specific_angular_momentum += specific;
+        max_speed = max_speed.max(particle.velocity.length());
+        if particle.id & SELECTED != 0 {
+            selection_moment += position.as_dvec2() * mass;
+            selection_mass += mass;
+        }
+        if particle.id & ID_MASK == PARTICLE_ID {
+            particle_position = position;
+            particle_mass = particle.mass;
+        }
+        if particle.id & CLUSTER != 0 {
+            cluster_moment += position.as_dvec2() * mass;
+            cluster_mass += mass;
+        }
+        if particle.mass > heaviest_mass {
+            heaviest_position = position;
+            heaviest_mass = particle.mass;
+        }
+        min_position = min_position.min(position);
+        max_position = max_position.max(position);
+        count += 1;
+    }
+
+    InfoOutput {
+        center_of_mass: (sum_moment / sum_mass).as_vec2(),
+        min_position,
+        max_position,
+        center_of_mass_velocity: (sum_momentum / sum_mass).as_vec2(),
+        centroid: (sum_position / count as f64).as_vec2(),
+        avg_velocity: (sum_velocity / count as f64).as_vec2(),
+        selection_center: (selection_moment / selection_mass).as_vec2(),
+        particle_position,
+        heaviest_position,
+        cluster_center: (cluster_moment / cluster_mass).as_vec2(),
+        total_mass: sum_mass as f32,
+        selection_mass: selection_mass as f32,
+        particle_mass,
+        heaviest_mass,
+        cluster_mass: cluster_mass as f32,
+        // Binned on the GPU, compared separately
+        percentile_radius: 0.0,
+        kinetic_energy: kinetic_energy as f32,
+        angular_momentum: angular_momentum as f32,
+        specific_angular_momentum: specific_angular_momentum as f32,
+        max_speed,
+        count,
+        _padding: 0,
+    }
+}
+
+/// The radius around the origin containing `percentile` of the mass
+fn percentile_radius(particles: &[Particle], percentile: f32) -> f32 {
+    let mut radii: Vec<_> = particles
+        .iter()
+        .filter(|particle| particle.mass != 0.0)
+        .map(|particle| (particle.position().length(), particle.mass as f64))
+        .collect();
+    radii.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let target_mass = radii.iter().map(|(_, mass)| mass).sum::<f64>() * percentile as f64;
+    let mut cumulative = 0.0;
+    for (radius, mass) in radii {
+        cumulative += mass;
+        if cumulative >= target_mass {
+            return radius as f32;
+        }
+    }
+    0.0
+}
+
+/// The largest absolute difference, the masses summed over many particles
+/// are compared relative to their size
+fn max_error(a: &InfoOutput, b: &InfoOutput) -> f32 {
+    [
+        a.center_of_mass - b.center_of_mass,
+        a.min_position - b.min_position,
+        a.max_position - b.max_position,
+        a.center_of_mass_velocity - b.center_of_mass_velocity,
+        a.centroid - b.centroid,
+        a.avg_velocity - b.avg_velocity,
+        a.selection_center - b.selection_center,
+        a.particle_position - b.particle_position,
+        a.heaviest_position - b.heaviest_position,
+        a.cluster_center - b.cluster_center,
+        Vec2::splat((a.total_mass - b.total_mass) / b.total_mass),
+        Vec2::splat((a.selection_mass - b.selection_mass) / b.selection_mass),
+        Vec2::splat(a.particle_mass - b.particle_mass),
+        Vec2::splat(a.heaviest_mass - b.heaviest_mass),
+        Vec2::splat((a.cluster_mass - b.cluster_mass) / b.cluster_mass),
+        Vec2::splat((a.kinetic_energy - b.kinetic_energy) / b.kinetic_energy),
+        Vec2::splat((a.angular_momentum - b.angular_momentum) / b.angular_momentum.abs()),
+        Vec2::splat(
+            (a.specific_angular_momentum - b.specific_angular_momentum)
+                / b.specific_angular_momentum.abs(),
+        ),
+        Vec2::splat(a.max_speed - b.max_speed),
+        Vec2::splat(a.count.abs_diff(b.count) as f32),
+    ]
+    .iter()
+    .map(|difference| difference.abs().max_element())
+    .fold(0.0, f32::max)
+}
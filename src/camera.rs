@@ -0,0 +1,136 @@
+use glam::DVec2;
+
+use crate::render::RenderModule;
+
+pub const MIN_ZOOM: f32 = 0.0001;
+pub const MAX_ZOOM: f32 = 10000.0;
+/// Pixels per world unit at a zoom of `1`, see `render.wgsl`
+pub const PIXELS_PER_UNIT: f32 = 250.0;
+
+/// The view of the renderer. Followed targets pull it along with critically damped springs,
+/// everything the user does moves it right away.
+pub struct Camera {
+    /// Negated world position of the center of the window
+    pub offset: DVec2,
+    pub zoom: f32,
+
+    /// Ease towards followed targets instead of snapping to them
+    pub smooth: bool,
+    /// Angular frequency of the springs, the view closes about 95% of the distance
+    /// to its target in `4.7 / stiffness` seconds
+    pub stiffness: f32,
+    /// Pixels a followed position can move away from the center before the view moves
+    pub offset_dead_zone: f32,
+    /// Relative change of a followed zoom before the view zooms, `0.1` is 10%
+    pub zoom_dead_zone: f32,
+
+    target_offset: DVec2,
+    /// `ln(zoom)`, so zooming in and out eases at the same rate
+    target_log_zoom: f32,
+    offset_velocity: DVec2,
+    log_zoom_velocity: f32,
+}
+
+impl Camera {
+    pub fn new(offset: DVec2, zoom: f32) -> Self {
+        let zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        Self {
+            offset,
+            zoom,
+
+            smooth: true,
+            stiffness: 5.0,
+            offset_dead_zone: 20.0,
+            zoom_dead_zone: 0.1,
+
+            target_offset: offset,
+            target_log_zoom: zoom.ln(),
+            offset_velocity: DVec2::ZERO,
+            log_zoom_velocity: 0.0,
+        }
+    }
+
+    /// Moves the view and stops easing
+    pub fn set_offset(&mut self, offset: DVec2) {
+        self.offset = offset;
+        self.target_offset = offset;
+        self.offset_velocity = DVec2::ZERO;
+    }
+
+    /// Zooms the view and stops easing
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.target_log_zoom = self.zoom.ln();
+        self.log_zoom_velocity = 0.0;
+    }
+
+    /// Pulls the view towards `offset` once it leaves the dead zone,
+    /// the target then stays on the edge of the dead zone
+    pub fn follow_offset(&mut self, offset: DVec2) {
+        let dead_zone = (self.offset_dead_zone / (PIXELS_PER_UNIT * self.zoom)) as f64;
+        self.target_offset = offset + (self.target_offset - offset).clamp_length_max(dead_zone);
+    }
+
+    /// Pulls the zoom towards `zoom` once it differs by more than the dead zone
+    pub fn follow_zoom(&mut self, zoom: f32) {
+        let log_zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM).ln();
+        let dead_zone = self.zoom_dead_zone.ln_1p();
+        self.target_log_zoom =
+            log_zoom + (self.target_log_zoom - log_zoom).clamp(-dead_zone, dead_zone);
+    }
+
+    /// Advances the springs by `delta_time` seconds and writes the view to `render_module`,
+    /// this is the only place the view of the renderer is written
+    pub fn update(&mut self, queue: &wgpu::Queue, render_module: &RenderModule, delta_time: f32) {
+        if self.smooth {
+            (self.offset, self.offset_velocity) = critically_damped(
+                self.offset,
+                self.offset_velocity,
+                self.target_offset,
+                self.stiffness as f64,
+                delta_time as f64,
+            );
+
+            let (log_zoom, log_zoom_velocity) = critically_damped(
+                self.zoom.ln() as f64,
+                self.log_zoom_velocity as f64,
+                self.target_log_zoom as f64,
+                self.stiffness as f64,
+                delta_time as f64,
+            );
+            self.zoom = (log_zoom.exp() as f32).clamp(MIN_ZOOM, MAX_ZOOM);
+            self.log_zoom_velocity = log_zoom_velocity as f32;
+        } else {
+            self.set_offset(self.target_offset);
+            self.set_zoom(self.target_log_zoom.exp());
+        }
+
+        render_module.update_offset(queue, self.offset.x, self.offset.y);
+        render_module.update_zoom(queue, self.zoom);
+    }
+}
+
+/// The exact step of a critically damped spring with angular frequency `stiffness`,
+/// it reaches `target` as fast as possible without overshooting
+fn critically_damped<T>(
+    position: T,
+    velocity: T,
+    target: T,
+    stiffness: f64,
+    delta_time: f64,
+) -> (T, T)
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f64, Output = T>,
+{
+    let displacement = position - target;
+    let decay = (-stiffness * delta_time).exp();
+    let change = (velocity + displacement * stiffness) * delta_time;
+
+    (
+        target + (displacement + change) * decay,
+        (velocity - change * stiffness) * decay,
+    )
+}
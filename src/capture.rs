@@ -3,14 +3,18 @@
 use log::info;
 use std::{io::Write, path::PathBuf};
 
-use crate::{render::RenderModule, utils::multiple_of};
+use crate::{readback::Readback, render::RenderModule, utils::multiple_of};
+
+/// Frames that can be in flight before capturing waits for the oldest one
+const READBACK_FRAMES: usize = 3;
 
 pub struct CaptureModule {
     pub enabled: bool,
 
-    pub staging_buffer: wgpu::Buffer,
     pub texture: wgpu::Texture,
 
+    readback: Readback,
+
     buffer_file: std::fs::File,
 }
 
@@ -23,12 +27,12 @@ impl CaptureModule {
     ) -> Self {
         let buffer_size =
             multiple_of(width, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) as u64 * height as u64 * 4;
-        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: buffer_size,
-            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let readback = Readback::new(
+            device,
+            "Capture Staging Buffer",
+            buffer_size,
+            READBACK_FRAMES,
+        );
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
@@ -60,8 +64,8 @@ impl CaptureModule {
         Self {
             enabled: false,
 
-            staging_buffer,
             texture,
+            readback,
             buffer_file: file,
         }
     }
@@ -73,14 +77,17 @@ impl CaptureModule {
         width: u32,
         height: u32,
     ) {
+        // The frames in flight still have the old size
+        self.flush(device);
+
         let buffer_size =
             multiple_of(width, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) as u64 * height as u64 * 4;
-        self.staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: buffer_size,
-            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        self.readback = Readback::new(
+            device,
+            "Capture Staging Buffer",
+            buffer_size,
+            READBACK_FRAMES,
+        );
 
         self.texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
@@ -108,7 +115,8 @@ impl CaptureModule {
         encoder: &mut wgpu::CommandEncoder,
         render_module: &RenderModule,
         particle_buffer: &wgpu::Buffer,
-        num_particles: u32,
+        label_buffer: &wgpu::Buffer,
+        indirect_buffer: &wgpu::Buffer,
     ) {
         if !self.enabled {
             return;
@@ -118,24 +126,41 @@ impl CaptureModule {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        render_module.begin_pass(encoder, &view, particle_buffer, num_particles);
+        render_module.begin_pass(
+            encoder,
+            &view,
+            particle_buffer,
+            label_buffer,
+            indirect_buffer,
+        );
     }
 
-    pub fn copy_texture_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
+    /// Copies the captured texture of `frame` to a staging buffer,
+    /// writes the frames in flight first when every staging buffer is taken, so none get dropped
+    pub fn copy_texture_to_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: u64,
+    ) {
         if !self.enabled {
             return;
         }
 
-        let bytes_per_row =
-            multiple_of(self.texture.width() * 4, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        if self.readback.is_full() {
+            self.flush(device);
+        }
+        let Some(staging_buffer) = self.readback.slot(frame) else {
+            return;
+        };
 
         encoder.copy_texture_to_buffer(
             self.texture.as_image_copy(),
             wgpu::ImageCopyBuffer {
-                buffer: &self.staging_buffer,
+                buffer: staging_buffer,
                 layout: wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: Some(bytes_per_row),
+                    bytes_per_row: Some(bytes_per_row(self.texture.width())),
                     rows_per_image: None, //Some(width * height),
                 },
             },
@@ -147,35 +172,41 @@ impl CaptureModule {
         );
     }
 
-    pub fn get_frame(&mut self, device: &wgpu::Device) {
-        if !self.enabled {
-            return;
-        }
+    /// Starts reading back the frame copied in this frame, has to be called after submitting it
+    pub fn map_readback(&mut self) {
+        self.readback.map_copies();
+    }
 
-        let slice = self.staging_buffer.slice(..);
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        slice.map_async(wgpu::MapMode::Read, move |v| tx.send(v).unwrap());
-
-        device.poll(wgpu::Maintain::wait()).panic_on_timeout();
-        if let Ok(Ok(())) = rx.recv() {
-            let data = slice.get_mapped_range();
-            let result: &[u8] = bytemuck::cast_slice(&data);
-
-            let texture_width = self.texture.width();
-            let bytes_per_row = multiple_of(texture_width, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * 4;
-            for y in 0..self.texture.height() {
-                let row = y * bytes_per_row;
-                self.buffer_file
-                    .write_all(&result[row as usize..row as usize + texture_width as usize * 4])
-                    .unwrap();
-            }
-
-            self.buffer_file.flush().unwrap();
-
-            drop(data);
-            self.staging_buffer.unmap();
-        } else {
-            panic!("Failed to get view texture.");
-        }
+    /// Appends the frames that arrived to `frame_buffer.bin` without waiting for the others,
+    /// keeps writing the frames in flight after capture is disabled
+    pub fn write_frames(&mut self, device: &wgpu::Device) {
+        let (width, height) = (self.texture.width(), self.texture.height());
+        let file = &mut self.buffer_file;
+        self.readback
+            .receive(device, |_, data| write_frame(file, width, height, data));
+    }
+
+    /// Writes every frame in flight
+    pub fn flush(&mut self, device: &wgpu::Device) {
+        let (width, height) = (self.texture.width(), self.texture.height());
+        let file = &mut self.buffer_file;
+        self.readback
+            .wait(device, |_, data| write_frame(file, width, height, data));
     }
 }
+
+/// Row pitch of the frames in the staging buffers
+fn bytes_per_row(width: u32) -> u32 {
+    multiple_of(width * 4, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+/// Writes the rows of a frame without the padding of the staging buffer
+fn write_frame(file: &mut std::fs::File, width: u32, height: u32, data: &[u8]) {
+    for y in 0..height {
+        let row = (y * bytes_per_row(width)) as usize;
+        file.write_all(&data[row..row + width as usize * 4])
+            .unwrap();
+    }
+
+    file.flush().unwrap();
+}
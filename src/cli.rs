@@ -1,13 +1,47 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::{
+    distribution::{Distribution, Radius},
+    physics::{MAX_FRAGMENTS, MAX_TIMESTEP_LEVEL},
+    preset::PresetKind,
+};
+
 /// A Newtonian Gravity Particle Simulation
+///
+/// The particles come from at most one of `--input`, `--image`, `--scene` and `--head-on`
 #[derive(Parser)]
-#[command()]
+#[command(group(clap::ArgGroup::new("source").args(["input", "image", "scene", "head_on"])))]
 pub struct Args {
     /// Total Particles
     #[arg(short, long, default_value_t = 4096)]
     pub particles: u32,
 
+    /// The most particles there can be at once, leaves room for emitted particles
+    ///
+    /// if lower than `--particles` there is no room
+    #[arg(long, default_value_t = 0)]
+    pub max_particles: u32,
+
+    /// The initial conditions the particles are generated from
+    #[arg(long, value_enum, default_value_t = PresetKind::Chunks)]
+    pub preset: PresetKind,
+
+    /// The mass distribution of generated particles
+    ///
+    /// `constant:<value>`, `uniform:<min>,<max>`, `log-uniform:<min>,<max>`,
+    /// `power-law:<min>,<max>[,<exponent>]` (Salpeter by default) or `gaussian:<mean>,<std-dev>`
+    #[arg(long, default_value = "constant:0.1")]
+    pub mass: Distribution,
+
+    /// The radius distribution of generated particles
+    ///
+    /// Takes the same distributions as `--mass` or `density:<density>`
+    /// to derive the radius from the mass
+    #[arg(long, default_value = "constant:0.1")]
+    pub radius: Radius,
+
     /// The framerate the simulation will run at  
     ///
     /// if `0` the simulation will run as fast as possible  
@@ -24,4 +58,106 @@ pub struct Args {
     /// Note: This WILL effect the simulation
     #[arg(short, long, default_value_t = 1.0/60.0)]
     pub time_scale: f32,
+
+    /// Integrate particle positions with double-single precision
+    ///
+    /// Keeps particles far away from the origin from jittering at the cost of performance
+    #[arg(short, long)]
+    pub extended_precision: bool,
+
+    /// The finest timestep level, particles in close encounters are integrated
+    /// with timesteps down to `time_scale / 2^max_timestep_level`
+    ///
+    /// if `0` every particle uses the same timestep
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=MAX_TIMESTEP_LEVEL as i64))]
+    pub max_timestep_level: u32,
+
+    /// Scales the individual particle timesteps, smaller is more accurate
+    #[arg(long, default_value_t = 0.5)]
+    pub timestep_accuracy: f32,
+
+    /// Only test for collisions at the end of each step
+    ///
+    /// Faster, but fast particles can pass through each other
+    #[arg(long)]
+    pub discrete_collisions: bool,
+
+    /// Relative impact speed above which colliding particles shatter into fragments
+    ///
+    /// if `0` particles never shatter
+    #[arg(long, default_value_t = 0.0)]
+    pub fragmentation_speed: f32,
+
+    /// The number of fragments a shattered particle splits into
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(2..=MAX_FRAGMENTS as i64))]
+    pub fragment_count: u32,
+
+    /// Particles whose fragments would be lighter than this don't shatter
+    #[arg(long, default_value_t = 0.001)]
+    pub min_fragment_mass: f32,
+
+    /// The speed fragments are ejected at relative to the impact speed
+    #[arg(long, default_value_t = 0.25)]
+    pub ejecta_speed: f32,
+
+    /// Load the particles from a file in the format of `example.csv` instead
+    ///
+    /// Overrides `--particles` with the number of rows
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+
+    /// Spawn particles on the bright pixels of a PNG or PPM image instead
+    ///
+    /// The masses and radii are drawn from `--mass` and `--radius`,
+    /// the other settings can be changed in the GUI or a scene file
+    #[arg(long)]
+    pub image: Option<PathBuf>,
+
+    /// Load a TOML scene file describing the physics parameters, the camera and the particles
+    ///
+    /// Its settings override the matching arguments
+    #[arg(long)]
+    pub scene: Option<PathBuf>,
+
+    /// Start with two particles colliding head-on at the given speed instead
+    #[arg(long)]
+    pub head_on: Option<f32>,
+
+    /// Append the statistics to a CSV file every `--stats-interval` steps
+    ///
+    /// The log can also be started and stopped in the `Simulation` window
+    #[arg(long, value_name = "FILE.csv")]
+    pub stats_log: Option<PathBuf>,
+
+    /// Steps between the rows of the statistics log
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub stats_interval: u32,
+
+    /// Time the follow statistics for growing particle counts on a headless device and exit
+    ///
+    /// Also reports the largest difference to the statistics computed on the CPU
+    #[arg(long)]
+    pub benchmark_follow: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_conflict() {
+        let parse = |args: &[&str]| Args::try_parse_from([&["particle_simulation"], args].concat());
+
+        assert!(parse(&["--input", "example.csv"]).is_ok());
+        assert!(parse(&["--head-on", "10"]).is_ok());
+        for args in [
+            ["--input", "example.csv", "--image", "image.png"],
+            ["--image", "image.png", "--scene", "scene.toml"],
+            ["--scene", "scene.toml", "--head-on", "10"],
+            ["--head-on", "10", "--input", "example.csv"],
+        ] {
+            let error = parse(&args).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
 }
@@ -0,0 +1,243 @@
+use std::{fmt::Write as _, io::Write as _, path::Path};
+
+use anyhow::{bail, Context};
+use glam::{DVec2, Vec2};
+
+use crate::particle::{Particle, ParticleSet};
+
+/// The columns of a particle, in the order of `example.csv`
+const COLUMNS: [&str; 6] = ["x", "y", "vx", "vy", "r", "m"];
+
+/// Reads particles from a file in the format of `example.csv`
+pub fn read_particles(path: &Path) -> anyhow::Result<ParticleSet> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read `{}`", path.display()))?;
+
+    parse_particles(&text).with_context(|| format!("Failed to parse `{}`", path.display()))
+}
+
+/// Writes particles in the format of `example.csv`, reading them back gives the same particles
+pub fn write_particles(path: &Path, particles: &[Particle]) -> anyhow::Result<()> {
+    let mut text = String::from("x-- y-- vx- vy- r-- m--\n");
+    for particle in particles {
+        // Both halves of a double-single fit into an `f64` without rounding
+        let position = particle.position();
+
+        writeln!(
+            text,
+            "{} {} {} {} {} {}",
+            position.x,
+            position.y,
+            particle.velocity.x,
+            particle.velocity.y,
+            particle.radius,
+            particle.mass
+        )?;
+    }
+
+    std::fs::File::create(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .with_context(|| format!("Failed to write `{}`", path.display()))
+}
+
+/// Parses one particle per line with the columns `x y vx vy r m`
+///
+/// Columns are separated by whitespace or commas. An optional header like
+/// `x-- y-- vx- vy- r-- m--` can list the columns in any order,
+/// empty lines and lines starting with `#` are skipped.
+pub fn parse_particles(text: &str) -> anyhow::Result<ParticleSet> {
+    let mut particles = ParticleSet::new();
+    // The field of each column in `COLUMNS` order
+    let mut order: Option<[usize; 6]> = None;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = split_fields(line);
+        if order.is_none() && particles.is_empty() && fields[0].parse::<f64>().is_err() {
+            order = Some(parse_header(&fields, line_number)?);
+            continue;
+        }
+        let order = *order.get_or_insert([0, 1, 2, 3, 4, 5]);
+
+        if fields.len() != COLUMNS.len() {
+            bail!(
+                "line {line_number}: expected {} columns but found {}",
+                COLUMNS.len(),
+                fields.len()
+            );
+        }
+
+        let mut values = [0.0f64; 6];
+        for (value, (&field, name)) in values.iter_mut().zip(order.iter().zip(COLUMNS)) {
+            let column = field + 1;
+            // Particles store `f32`s, the position split into two of them
+            *value = match fields[field].parse::<f64>() {
+                Ok(value) if value.is_finite() && (value as f32).is_finite() => value,
+                _ => bail!(
+                    "line {line_number}, column {column} ({name}): `{}` is not a finite number",
+                    fields[field]
+                ),
+            };
+
+            // A mass of `0` would mark the particle as dead
+            if name == "m" && *value as f32 <= 0.0 {
+                bail!("line {line_number}, column {column} ({name}): must be positive");
+            }
+            if name == "r" && *value < 0.0 {
+                bail!("line {line_number}, column {column} ({name}): must not be negative");
+            }
+        }
+
+        let [x, y, vx, vy, r, m] = values;
+        particles.push(Particle::new(
+            DVec2::new(x, y),
+            Vec2::new(vx as f32, vy as f32),
+            r as f32,
+            m as f32,
+        ));
+    }
+
+    if particles.is_empty() {
+        bail!("no particles found");
+    }
+
+    Ok(particles)
+}
+
+fn split_fields(line: &str) -> Vec<&str> {
+    if line.contains(',') {
+        line.split(',').map(str::trim).collect()
+    } else {
+        line.split_whitespace().collect()
+    }
+}
+
+/// Finds the field of every column, names may be padded with `-` like in `example.csv`
+fn parse_header(fields: &[&str], line_number: usize) -> anyhow::Result<[usize; 6]> {
+    let mut order = [usize::MAX; 6];
+    for (field, name) in fields.iter().enumerate() {
+        let column = field + 1;
+        let name = name.trim_end_matches('-').to_lowercase();
+        let index = match name.as_str() {
+            "radius" => 4,
+            "mass" => 5,
+            name => match COLUMNS.iter().position(|&c| c == name) {
+                Some(index) => index,
+                None => bail!("line {line_number}, column {column}: unknown column `{name}`"),
+            },
+        };
+
+        if order[index] != usize::MAX {
+            bail!(
+                "line {line_number}, column {column}: duplicate column `{}`",
+                COLUMNS[index]
+            );
+        }
+        order[index] = field;
+    }
+
+    if let Some(missing) = order.iter().position(|&field| field == usize::MAX) {
+        bail!("line {line_number}: missing column `{}`", COLUMNS[missing]);
+    }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_loads() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("example.csv");
+        let particles = read_particles(&path).unwrap();
+
+        assert_eq!(particles.len(), 5);
+        particles.validate().unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut particles = ParticleSet::from(vec![
+            Particle::new(
+                DVec2::new(1e8 + 0.125, -0.1),
+                Vec2::new(0.3, -7.5),
+                0.25,
+                1e-3,
+            ),
+            Particle::new(DVec2::new(-2.5, 3e-7), Vec2::ZERO, 0.0, 12.0),
+        ]);
+        particles[1].velocity = Vec2::new(f32::MIN_POSITIVE, 1e30);
+
+        let path = std::env::temp_dir().join(format!("round_trip_{}.csv", std::process::id()));
+        write_particles(&path, &particles).unwrap();
+        let read = read_particles(&path);
+        std::fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+
+        assert_eq!(read.len(), particles.len());
+        for (read, written) in read.iter().zip(particles.iter()) {
+            assert_eq!(read.position(), written.position());
+            assert_eq!(read.velocity, written.velocity);
+            assert_eq!(read.radius, written.radius);
+            assert_eq!(read.mass, written.mass);
+        }
+    }
+
+    #[test]
+    fn headers_and_separators() {
+        let text = "# comment\n\nm,radius,x,y,vy,vx\n2, 0.5, 1, -1, 4, 3\n";
+        let particles = parse_particles(text).unwrap();
+
+        assert_eq!(particles.len(), 1);
+        let particle = &particles[0];
+        assert_eq!(particle.position(), DVec2::new(1.0, -1.0));
+        assert_eq!(particle.velocity, Vec2::new(3.0, 4.0));
+        assert_eq!((particle.radius, particle.mass), (0.5, 2.0));
+
+        let particles = parse_particles("1 2 3 4 5 6\n7 8 9 10 11 12").unwrap();
+        assert_eq!(particles[1].mass, 12.0);
+    }
+
+    #[test]
+    fn errors_name_line_and_column() {
+        let error = |text: &str| format!("{:#}", parse_particles(text).err().unwrap());
+        let header = "x-- y-- vx- vy- r-- m--\n";
+
+        assert_eq!(
+            error(&format!("{header}0 0 0 0 1 1\n\n0 0 0 0 1 0\n")),
+            "line 4, column 6 (m): must be positive"
+        );
+        assert_eq!(
+            error(&format!("{header}0 0 0 0 -1 1\n")),
+            "line 2, column 5 (r): must not be negative"
+        );
+        assert_eq!(
+            error(&format!("{header}0 0 nan 0 1 1\n")),
+            "line 2, column 3 (vx): `nan` is not a finite number"
+        );
+        assert_eq!(
+            error("m x y vx vy r\n1e300 0 0 0 0 1\n"),
+            "line 2, column 1 (m): `1e300` is not a finite number"
+        );
+        assert_eq!(
+            error(&format!("{header}0 0 0 0 1\n")),
+            "line 2: expected 6 columns but found 5"
+        );
+        assert_eq!(
+            error("x y vx vy r mass m\n"),
+            "line 1, column 7: duplicate column `m`"
+        );
+        assert_eq!(error("x y vx vy r\n"), "line 1: missing column `m`");
+        assert_eq!(
+            error("x y vx vy r w\n"),
+            "line 1, column 6: unknown column `w`"
+        );
+        assert_eq!(error(header), "no particles found");
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    f32::consts::{PI, TAU},
+    str::FromStr,
+};
+
+use rand::Rng;
+
+/// Exponent of the Salpeter initial mass function `m^-2.35`
+pub const SALPETER_EXPONENT: f32 = 2.35;
+
+/// Bins of the histogram preview
+const HISTOGRAM_BINS: usize = 32;
+/// Samples drawn for the histogram preview
+const HISTOGRAM_SAMPLES: usize = 10000;
+
+/// A distribution of positive values
+///
+/// Parsed from `constant:<value>`, `uniform:<min>,<max>`, `log-uniform:<min>,<max>`,
+/// `power-law:<min>,<max>[,<exponent>]` (Salpeter by default) or `gaussian:<mean>,<std-dev>`
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Distribution {
+    Constant(f32),
+    Uniform {
+        min: f32,
+        max: f32,
+    },
+    LogUniform {
+        min: f32,
+        max: f32,
+    },
+    /// `p(x) ∝ x^-exponent` between `min` and `max`
+    PowerLaw {
+        min: f32,
+        max: f32,
+        exponent: f32,
+    },
+    /// Negative samples are redrawn
+    Gaussian {
+        mean: f32,
+        std_dev: f32,
+    },
+}
+
+impl Distribution {
+    const NAMES: [&'static str; 5] = [
+        "Constant",
+        "Uniform",
+        "Log-uniform",
+        "Power Law",
+        "Gaussian",
+    ];
+
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Uniform { min, max } => min + (max - min) * rng.gen::<f32>(),
+            Distribution::LogUniform { min, max } => {
+                (min.ln() + (max.ln() - min.ln()) * rng.gen::<f32>()).exp()
+            }
+            Distribution::PowerLaw { min, max, exponent } => {
+                let u: f32 = rng.gen();
+                let k = 1.0 - exponent;
+                if k.abs() < 1e-4 {
+                    // `x^-1` is log-uniform
+                    (min.ln() + (max.ln() - min.ln()) * u).exp()
+                } else {
+                    (min.powf(k) + u * (max.powf(k) - min.powf(k))).powf(1.0 / k)
+                }
+            }
+            Distribution::Gaussian { mean, std_dev } => {
+                for _ in 0..16 {
+                    let value = mean + std_dev * gaussian(rng);
+                    if value > 0.0 {
+                        return value;
+                    }
+                }
+                mean.abs()
+            }
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Distribution::Constant(_) => 0,
+            Distribution::Uniform { .. } => 1,
+            Distribution::LogUniform { .. } => 2,
+            Distribution::PowerLaw { .. } => 3,
+            Distribution::Gaussian { .. } => 4,
+        }
+    }
+
+    /// The distribution at `index` in `NAMES` spanning roughly the same values as this one
+    fn with_index(&self, index: usize) -> Self {
+        let (min, max) = match *self {
+            Distribution::Constant(value) => (value * 0.1, value),
+            Distribution::Uniform { min, max }
+            | Distribution::LogUniform { min, max }
+            | Distribution::PowerLaw { min, max, .. } => (min, max),
+            Distribution::Gaussian { mean, std_dev } => {
+                ((mean - std_dev).max(mean * 0.1), mean + std_dev)
+            }
+        };
+
+        match index {
+            0 => Distribution::Constant(max),
+            1 => Distribution::Uniform { min, max },
+            2 => Distribution::LogUniform { min, max },
+            3 => Distribution::PowerLaw {
+                min,
+                max,
+                exponent: SALPETER_EXPONENT,
+            },
+            _ => Distribution::Gaussian {
+                mean: (min + max) / 2.0,
+                std_dev: (max - min) / 4.0,
+            },
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, label: &str) {
+        let mut index = self.index();
+        egui::ComboBox::from_label(label)
+            .selected_text(Self::NAMES[index])
+            .show_ui(ui, |ui| {
+                for (i, name) in Self::NAMES.into_iter().enumerate() {
+                    ui.selectable_value(&mut index, i, name);
+                }
+            });
+        if index != self.index() {
+            *self = self.with_index(index);
+        }
+
+        let value = |ui: &mut egui::Ui, value: &mut f32, prefix: &str| {
+            ui.add(
+                egui::DragValue::new(value)
+                    .speed(0.001)
+                    .clamp_range(1e-6..=f32::MAX)
+                    .prefix(prefix),
+            );
+        };
+        match self {
+            Distribution::Constant(v) => value(ui, v, "Value "),
+            Distribution::Uniform { min, max } | Distribution::LogUniform { min, max } => {
+                value(ui, min, "Min ");
+                value(ui, max, "Max ");
+            }
+            Distribution::PowerLaw { min, max, exponent } => {
+                value(ui, min, "Min ");
+                value(ui, max, "Max ");
+                ui.add(
+                    egui::DragValue::new(exponent)
+                        .speed(0.01)
+                        .prefix("Exponent "),
+                );
+            }
+            Distribution::Gaussian { mean, std_dev } => {
+                value(ui, mean, "Mean ");
+                value(ui, std_dev, "Std Dev ");
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Distribution {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, values) = s.split_once(':').unwrap_or((s, ""));
+        let values = values
+            .split(',')
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| {
+                v.trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| format!("`{v}` is not a finite number"))
+            })
+            .collect::<Result<Vec<f32>, _>>()?;
+
+        let distribution = match (kind, values.as_slice()) {
+            ("constant", &[value]) => Distribution::Constant(value),
+            ("uniform", &[min, max]) => Distribution::Uniform { min, max },
+            ("log-uniform", &[min, max]) => Distribution::LogUniform { min, max },
+            ("power-law", &[min, max]) => Distribution::PowerLaw {
+                min,
+                max,
+                exponent: SALPETER_EXPONENT,
+            },
+            ("power-law", &[min, max, exponent]) => Distribution::PowerLaw { min, max, exponent },
+            ("gaussian", &[mean, std_dev]) => Distribution::Gaussian { mean, std_dev },
+            ("constant" | "uniform" | "log-uniform" | "power-law" | "gaussian", _) => {
+                return Err(format!("wrong number of values for `{kind}`"))
+            }
+            _ => return Err(format!("unknown distribution `{kind}`")),
+        };
+
+        match distribution {
+            Distribution::Constant(value) if value <= 0.0 => Err("must be positive".into()),
+            Distribution::Uniform { min, max }
+            | Distribution::LogUniform { min, max }
+            | Distribution::PowerLaw { min, max, .. }
+                if min <= 0.0 || max < min =>
+            {
+                Err("needs `0 < min <= max`".into())
+            }
+            Distribution::Gaussian { mean, std_dev } if mean <= 0.0 || std_dev < 0.0 => {
+                Err("needs a positive mean and standard deviation".into())
+            }
+            distribution => Ok(distribution),
+        }
+    }
+}
+
+/// How the radius of a particle is picked
+///
+/// Parsed like a [`Distribution`] or from `density:<density>`
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Radius {
+    Distribution(Distribution),
+    /// Derived from the mass of discs with a constant area density
+    Density(f32),
+}
+
+impl TryFrom<String> for Radius {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for Radius {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("density:") {
+            Some(density) => match density.trim().parse::<f32>() {
+                Ok(density) if density > 0.0 && density.is_finite() => Ok(Radius::Density(density)),
+                _ => Err(format!("`{density}` is not a positive density")),
+            },
+            None => s.parse().map(Radius::Distribution),
+        }
+    }
+}
+
+/// The mass and radius distributions of generated particles
+#[derive(Clone, Copy, PartialEq)]
+pub struct ParticleProperties {
+    pub mass: Distribution,
+    pub radius: Radius,
+}
+
+impl ParticleProperties {
+    pub fn sample_mass(&self, rng: &mut impl Rng) -> f32 {
+        self.mass.sample(rng)
+    }
+
+    /// The radius of a particle of `mass`
+    pub fn radius(&self, rng: &mut impl Rng, mass: f32) -> f32 {
+        match self.radius {
+            Radius::Distribution(distribution) => distribution.sample(rng),
+            Radius::Density(density) => (mass / (density * PI)).sqrt(),
+        }
+    }
+
+    /// Edits both distributions and shows a histogram of them
+    pub fn ui(&mut self, ui: &mut egui::Ui, preview: &mut Option<Preview>) {
+        self.distributions_ui(ui);
+
+        let preview = match preview {
+            Some(preview) if preview.properties == *self => preview,
+            preview => preview.insert(Preview::new(*self)),
+        };
+        ui.label("Mass");
+        preview.mass.ui(ui);
+        ui.label("Radius");
+        preview.radius.ui(ui);
+    }
+
+    /// Edits both distributions without a preview
+    pub fn distributions_ui(&mut self, ui: &mut egui::Ui) {
+        self.mass.ui(ui, "Mass");
+
+        let mut density = matches!(self.radius, Radius::Density(_));
+        ui.checkbox(&mut density, "Radius from Density");
+        match (&mut self.radius, density) {
+            (Radius::Density(density), true) => {
+                ui.add(
+                    egui::DragValue::new(density)
+                        .speed(0.1)
+                        .clamp_range(1e-6..=f32::MAX)
+                        .prefix("Density "),
+                );
+            }
+            (Radius::Distribution(distribution), false) => distribution.ui(ui, "Radius"),
+            (Radius::Density(_), false) => {
+                self.radius = Radius::Distribution(Distribution::Constant(0.1));
+            }
+            (Radius::Distribution(_), true) => self.radius = Radius::Density(3.0),
+        }
+    }
+}
+
+impl Default for ParticleProperties {
+    fn default() -> Self {
+        Self {
+            mass: Distribution::Constant(0.1),
+            radius: Radius::Distribution(Distribution::Constant(0.1)),
+        }
+    }
+}
+
+/// Histograms of samples drawn from [`ParticleProperties`]
+pub struct Preview {
+    properties: ParticleProperties,
+    mass: Histogram,
+    radius: Histogram,
+}
+
+impl Preview {
+    fn new(properties: ParticleProperties) -> Self {
+        let mut rng = rand::thread_rng();
+        let (masses, radii): (Vec<f32>, Vec<f32>) = (0..HISTOGRAM_SAMPLES)
+            .map(|_| {
+                let mass = properties.sample_mass(&mut rng);
+                (mass, properties.radius(&mut rng, mass))
+            })
+            .unzip();
+
+        Self {
+            properties,
+            mass: Histogram::new(&masses),
+            radius: Histogram::new(&radii),
+        }
+    }
+}
+
+struct Histogram {
+    min: f32,
+    max: f32,
+    bins: [u32; HISTOGRAM_BINS],
+}
+
+impl Histogram {
+    fn new(samples: &[f32]) -> Self {
+        let min = samples.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = samples.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        let mut bins = [0; HISTOGRAM_BINS];
+        for &sample in samples {
+            let bin = ((sample - min) / (max - min) * HISTOGRAM_BINS as f32) as usize;
+            bins[bin.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+
+        Self { min, max, bins }
+    }
+
+    fn ui(&self, ui: &mut egui::Ui) {
+        let (response, painter) = ui.allocate_painter(
+            egui::Vec2::new(ui.available_width().max(100.0), 40.0),
+            egui::Sense::hover(),
+        );
+        let rect = response.rect;
+        let peak = self.bins.iter().copied().max().unwrap_or(1).max(1) as f32;
+        let width = rect.width() / HISTOGRAM_BINS as f32;
+
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        for (i, &count) in self.bins.iter().enumerate() {
+            let height = rect.height() * count as f32 / peak;
+            let x = rect.left() + i as f32 * width;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::Pos2::new(x, rect.bottom() - height),
+                    egui::Pos2::new(x + width, rect.bottom()),
+                ),
+                0.0,
+                ui.visuals().selection.bg_fill,
+            );
+        }
+
+        ui.horizontal(|ui| {
+            ui.small(format!("{:.4}", self.min));
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.small(format!("{:.4}", self.max));
+            });
+        });
+    }
+}
+
+/// A normally distributed number using the Box-Muller transform
+pub fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u: f32 = rng.gen_range(f32::EPSILON..=1.0);
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn parse(s: &str) -> Result<Distribution, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_distributions() {
+        assert!(parse("constant:0.5") == Ok(Distribution::Constant(0.5)));
+        assert!(parse("uniform: 1, 2") == Ok(Distribution::Uniform { min: 1.0, max: 2.0 }));
+        assert!(
+            parse("log-uniform:0.1,10")
+                == Ok(Distribution::LogUniform {
+                    min: 0.1,
+                    max: 10.0
+                })
+        );
+        assert!(
+            parse("power-law:0.01,1")
+                == Ok(Distribution::PowerLaw {
+                    min: 0.01,
+                    max: 1.0,
+                    exponent: SALPETER_EXPONENT
+                })
+        );
+        assert!(
+            parse("power-law:1,2,1.5")
+                == Ok(Distribution::PowerLaw {
+                    min: 1.0,
+                    max: 2.0,
+                    exponent: 1.5
+                })
+        );
+        assert!(
+            parse("gaussian:1,0.2")
+                == Ok(Distribution::Gaussian {
+                    mean: 1.0,
+                    std_dev: 0.2
+                })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_distributions() {
+        let error = |s: &str| parse(s).err().unwrap();
+
+        assert_eq!(error("cauchy:1,2"), "unknown distribution `cauchy`");
+        assert_eq!(error("uniform:1"), "wrong number of values for `uniform`");
+        assert_eq!(error("constant"), "wrong number of values for `constant`");
+        assert_eq!(error("constant:inf"), "`inf` is not a finite number");
+        assert_eq!(error("constant:x"), "`x` is not a finite number");
+        assert_eq!(error("constant:0"), "must be positive");
+        assert_eq!(error("uniform:0,1"), "needs `0 < min <= max`");
+        assert_eq!(error("log-uniform:2,1"), "needs `0 < min <= max`");
+        assert_eq!(
+            error("gaussian:1,-1"),
+            "needs a positive mean and standard deviation"
+        );
+    }
+
+    #[test]
+    fn parses_radii() {
+        assert!("density:2".parse::<Radius>() == Ok(Radius::Density(2.0)));
+        assert!(
+            "constant:0.1".parse::<Radius>()
+                == Ok(Radius::Distribution(Distribution::Constant(0.1)))
+        );
+        assert_eq!(
+            "density:0".parse::<Radius>().err().unwrap(),
+            "`0` is not a positive density"
+        );
+        assert_eq!(
+            "density:nan".parse::<Radius>().err().unwrap(),
+            "`nan` is not a positive density"
+        );
+        assert_eq!(
+            "uniform:2,1".parse::<Radius>().err().unwrap(),
+            "needs `0 < min <= max`"
+        );
+    }
+
+    #[test]
+    fn samples_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for distribution in [
+            "uniform:1,2",
+            "log-uniform:1,2",
+            "power-law:1,2",
+            "power-law:1,2,1",
+        ] {
+            let distribution = parse(distribution).unwrap();
+            for _ in 0..1000 {
+                let sample = distribution.sample(&mut rng);
+                assert!((1.0..=2.0).contains(&sample), "{sample}");
+            }
+        }
+
+        let gaussian = parse("gaussian:0.1,1").unwrap();
+        assert!((0..1000).all(|_| gaussian.sample(&mut rng) > 0.0));
+    }
+}
@@ -0,0 +1,207 @@
+use egui::Widget;
+use glam::{DVec2, Vec2};
+use rand::Rng;
+
+use crate::{
+    distribution::{Distribution, ParticleProperties, Radius},
+    particle::{Particle, ParticleSet},
+};
+
+/// Where on an emitter particles appear
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Shape {
+    Point,
+    /// A line through the position, perpendicular to the emission direction
+    Line {
+        length: f32,
+    },
+    /// A circle around the position, the emission direction is relative to its outward normal
+    Ring {
+        radius: f32,
+    },
+}
+
+impl Shape {
+    /// Every shape with default parameters
+    pub const DEFAULTS: [Shape; 3] = [
+        Shape::Point,
+        Shape::Line { length: 10.0 },
+        Shape::Ring { radius: 20.0 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Point => "Point",
+            Shape::Line { .. } => "Line",
+            Shape::Ring { .. } => "Ring",
+        }
+    }
+
+    /// A random point on the shape relative to its center and the emission direction there
+    fn sample(&self, rng: &mut impl Rng, direction: Vec2) -> (Vec2, Vec2) {
+        match *self {
+            Shape::Point => (Vec2::ZERO, direction),
+            Shape::Line { length } => {
+                let offset = direction.perp() * length * (rng.gen::<f32>() - 0.5);
+                (offset, direction)
+            }
+            Shape::Ring { radius } => {
+                let normal = Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU));
+                (normal * radius, normal.rotate(direction))
+            }
+        }
+    }
+}
+
+/// Injects particles into the running simulation
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Emitter {
+    pub enabled: bool,
+    pub shape: Shape,
+    pub position: [f64; 2],
+    /// Angle of the emission direction in degrees, counterclockwise from the x axis.
+    /// For rings it's relative to the outward normal, `90` emits along the ring.
+    pub direction: f32,
+    /// Full angle in degrees the directions are spread over
+    pub spread: f32,
+    pub speed: Distribution,
+    /// Particles per unit of simulation time
+    pub rate: f32,
+    pub mass: Distribution,
+    pub radius: Radius,
+
+    /// Fraction of a particle carried over to the next frame
+    #[serde(skip)]
+    remainder: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            shape: Shape::Point,
+            position: [0.0; 2],
+            direction: 0.0,
+            spread: 10.0,
+            speed: Distribution::Constant(5.0),
+            rate: 600.0,
+            mass: Distribution::Constant(0.1),
+            radius: Radius::Distribution(Distribution::Constant(0.1)),
+            remainder: 0.0,
+        }
+    }
+}
+
+impl Emitter {
+    /// An emitter with the default settings at `position`
+    pub fn at(position: DVec2) -> Self {
+        Self {
+            position: position.to_array(),
+            ..Default::default()
+        }
+    }
+
+    /// Appends the particles emitted over `delta_time` to `particles`
+    pub fn emit(&mut self, delta_time: f32, particles: &mut ParticleSet) {
+        if !self.enabled {
+            return;
+        }
+
+        let expected = self.rate.max(0.0) * delta_time + self.remainder;
+        let count = expected.floor();
+        self.remainder = expected - count;
+
+        let mut rng = rand::thread_rng();
+        let properties = self.properties();
+        let direction = Vec2::from_angle(self.direction.to_radians());
+        for _ in 0..count as usize {
+            let (offset, direction) = self.shape.sample(&mut rng, direction);
+            let angle = (rng.gen::<f32>() - 0.5) * self.spread.to_radians();
+            let velocity = Vec2::from_angle(angle).rotate(direction) * self.speed.sample(&mut rng);
+            // Spread over the frame so fast streams don't come out in clumps
+            let offset = offset + velocity * delta_time * rng.gen::<f32>();
+
+            let mass = properties.sample_mass(&mut rng);
+            particles.push(Particle::new(
+                DVec2::from_array(self.position) + offset.as_dvec2(),
+                velocity,
+                properties.radius(&mut rng, mass),
+                mass,
+            ));
+        }
+    }
+
+    fn properties(&self) -> ParticleProperties {
+        ParticleProperties {
+            mass: self.mass,
+            radius: self.radius,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Enabled");
+        egui::ComboBox::from_label("Shape")
+            .selected_text(self.shape.name())
+            .show_ui(ui, |ui| {
+                for option in Shape::DEFAULTS {
+                    let selected = option.name() == self.shape.name();
+                    if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                        self.shape = option;
+                    }
+                }
+            });
+        match &mut self.shape {
+            Shape::Point => {}
+            Shape::Line { length } => {
+                egui::DragValue::new(length)
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX)
+                    .prefix("Length ")
+                    .ui(ui);
+            }
+            Shape::Ring { radius } => {
+                egui::DragValue::new(radius)
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX)
+                    .prefix("Radius ")
+                    .ui(ui);
+            }
+        }
+
+        ui.horizontal(|ui| {
+            egui::DragValue::new(&mut self.position[0])
+                .speed(0.1)
+                .prefix("x ")
+                .ui(ui);
+            egui::DragValue::new(&mut self.position[1])
+                .speed(0.1)
+                .prefix("y ")
+                .ui(ui);
+        });
+        egui::DragValue::new(&mut self.direction)
+            .speed(1.0)
+            .suffix("°")
+            .prefix("Direction ")
+            .ui(ui);
+        egui::DragValue::new(&mut self.spread)
+            .speed(1.0)
+            .clamp_range(0.0..=360.0)
+            .suffix("°")
+            .prefix("Spread ")
+            .ui(ui);
+        self.speed.ui(ui, "Speed");
+        egui::DragValue::new(&mut self.rate)
+            .speed(1.0)
+            .clamp_range(0.0..=f32::MAX)
+            .prefix("Rate ")
+            .suffix(" / time")
+            .ui(ui);
+
+        let mut properties = self.properties();
+        properties.distributions_ui(ui);
+        self.mass = properties.mass;
+        self.radius = properties.radius;
+    }
+}
@@ -2,32 +2,191 @@ use std::borrow::Cow;
 
 use glam::Vec2;
 
+use crate::{
+    histogram::{Distributions, DISTRIBUTIONS_SIZE},
+    readback::Readback,
+};
+
 #[derive(Default, Clone, Copy, bytemuck::Zeroable)]
+#[repr(C)]
 pub struct InfoOutput {
+    /// Mass weighted average position
     pub center_of_mass: Vec2,
     pub min_position: Vec2,
     pub max_position: Vec2,
+    /// Total momentum over the total mass
+    pub center_of_mass_velocity: Vec2,
+    /// Unweighted average position
+    pub centroid: Vec2,
+    /// Unweighted average velocity
     pub avg_velocity: Vec2,
+    /// Center of mass of the selected particles
+    pub selection_center: Vec2,
+    /// Center of mass of the particle [`FollowTarget::Particle`] and its fragments
+    pub particle_position: Vec2,
+    pub heaviest_position: Vec2,
+    /// Center of mass of the largest bound cluster, see [`crate::group::GroupModule`]
+    pub cluster_center: Vec2,
+    pub total_mass: f32,
+    /// `0` when nothing is selected
+    pub selection_mass: f32,
+    /// `0` when the particle doesn't exist
+    pub particle_mass: f32,
+    pub heaviest_mass: f32,
+    /// `0` when no cluster is flagged
+    pub cluster_mass: f32,
+    /// Radius around the position followed in the last frame containing
+    /// [`FollowModule::zoom_percentile`] of the mass, only measured with auto zoom
+    pub percentile_radius: f32,
+    /// Sum of `m v² / 2`
+    pub kinetic_energy: f32,
+    /// Sum of `m (r × v)` around the origin, conserved by collisions but not by gravity
+    pub angular_momentum: f32,
+    /// Sum of `r × v` around the origin. The gravity of `physics.wgsl` changes the velocities
+    /// by the same amount regardless of the mass, so it conserves this and the sum of the
+    /// velocities instead of the angular momentum and the momentum.
+    pub specific_angular_momentum: f32,
+    pub max_speed: f32,
+    /// Particles with mass
+    pub count: u32,
+    pub _padding: u32,
 }
 
 unsafe impl bytemuck::Pod for InfoOutput {}
 
+/// The uniform parameters of `follow.wgsl`
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+pub struct FollowParams {
+    /// Id of the followed particle
+    pub particle: u32,
+    /// Fraction of the mass inside `InfoOutput::percentile_radius`
+    pub percentile: f32,
+    /// The position the percentile radius and the surface density are measured from
+    pub center: Vec2,
+    /// Space the speed and mass bins logarithmically
+    pub log_bins: u32,
+    pub _padding: u32,
+}
+
+/// The point the camera is kept centered on
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FollowTarget {
+    CenterOfMass,
+    Centroid,
+    /// The particle with this id, see [`crate::particle::Particle::id`]
+    Particle {
+        id: u32,
+    },
+    /// The particles selected with shift and the left mouse button
+    Selection,
+    Heaviest,
+    /// The heaviest group held together by its own gravity, see [`crate::group::Group::is_bound`]
+    LargestCluster,
+}
+
+impl FollowTarget {
+    /// Every target with default parameters
+    pub const DEFAULTS: [FollowTarget; 6] = [
+        FollowTarget::CenterOfMass,
+        FollowTarget::Centroid,
+        FollowTarget::Particle { id: 0 },
+        FollowTarget::Selection,
+        FollowTarget::Heaviest,
+        FollowTarget::LargestCluster,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FollowTarget::CenterOfMass => "Center of Mass",
+            FollowTarget::Centroid => "Centroid",
+            FollowTarget::Particle { .. } => "Particle",
+            FollowTarget::Selection => "Selection",
+            FollowTarget::Heaviest => "Heaviest Particle",
+            FollowTarget::LargestCluster => "Largest Bound Cluster",
+        }
+    }
+
+    /// The position of the target, `None` if it doesn't exist
+    pub fn position(&self, info: &InfoOutput) -> Option<Vec2> {
+        match self {
+            FollowTarget::CenterOfMass => Some(info.center_of_mass),
+            FollowTarget::Centroid => Some(info.centroid),
+            FollowTarget::Particle { .. } => {
+                (info.particle_mass > 0.0).then_some(info.particle_position)
+            }
+            FollowTarget::Selection => (info.selection_mass > 0.0).then_some(info.selection_center),
+            FollowTarget::Heaviest => (info.heaviest_mass > 0.0).then_some(info.heaviest_position),
+            FollowTarget::LargestCluster => {
+                (info.cluster_mass > 0.0).then_some(info.cluster_center)
+            }
+        }
+    }
+}
+
+/// The most workgroups of the first reduction pass, must match `MAX_REDUCE_WORKGROUPS`
+/// in `follow.wgsl`
+const MAX_REDUCE_WORKGROUPS: u64 = 128;
+/// Size of a `Partial` in `follow.wgsl`, padded to its 8 byte alignment
+const PARTIAL_SIZE: u64 = 120;
+/// Must match `HISTOGRAM_BINS` in `follow.wgsl`
+pub const HISTOGRAM_BINS: usize = 256;
+/// Must match `HISTOGRAM_OCTAVES` in `follow.wgsl`
+pub const HISTOGRAM_OCTAVES: f32 = 20.0;
+/// Must match `DISTRIBUTION_BINS` in `follow.wgsl`
+pub const DISTRIBUTION_BINS: usize = 64;
+/// Must match `MASS_SCALE` in `follow.wgsl`
+pub const MASS_SCALE: f32 = 1073741824.0;
+/// Statistics that can be in flight, later frames skip the readback
+const READBACK_FRAMES: usize = 3;
+
+/// Measures the statistics of the particles on the GPU, which the camera follows
 pub struct FollowModule {
+    /// Moves the camera with [`Self::target`], the statistics are measured whenever
+    /// [`Self::begin_pass`] runs
     pub enabled: bool,
-    pub center_of_mass: bool,
+    /// `None` leaves the camera where it is
+    pub target: Option<FollowTarget>,
     pub auto_zoom: bool,
+    /// Auto zoom fits the radius containing this fraction of the mass,
+    /// so a few ejected particles don't zoom out
+    pub zoom_percentile: f32,
+    /// Room left around the percentile radius relative to it
+    pub zoom_margin: f32,
+    /// Measures the [`Distributions`] as well
+    pub distributions: bool,
+    /// Space the speed and mass bins logarithmically
+    pub log_bins: bool,
 
     pub info: InfoOutput,
 
     position_buffer: wgpu::Buffer,
-    staging_buffer: wgpu::Buffer,
+    readback: Readback,
+    partial_buffer: wgpu::Buffer,
+    dispatch_buffer: wgpu::Buffer,
+    param_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    distribution_buffer: wgpu::Buffer,
+    /// The distribution buffer followed by the histogram buffer
+    distribution_readback: Readback,
 
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_groups: [wgpu::BindGroup; 2],
-    pipeline: wgpu::ComputePipeline,
+    dispatch_bind_group: wgpu::BindGroup,
+    prepare_pipeline: wgpu::ComputePipeline,
+    reduce_pipeline: wgpu::ComputePipeline,
+    finish_pipeline: wgpu::ComputePipeline,
+    bin_pipeline: wgpu::ComputePipeline,
+    percentile_pipeline: wgpu::ComputePipeline,
+    distribution_pipeline: wgpu::ComputePipeline,
 }
 
 impl FollowModule {
-    pub fn new(device: &wgpu::Device, particle_buffers: &[wgpu::Buffer; 2]) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer; 2],
+        allocator_buffer: &wgpu::Buffer,
+    ) -> Self {
         let follow_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
             source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("follow.wgsl"))),
@@ -42,13 +201,55 @@ impl FollowModule {
             mapped_at_creation: false,
         });
 
-        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: std::mem::size_of::<InfoOutput>() as u64,
-            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        let readback = Readback::new(
+            device,
+            "Follow Staging Buffer",
+            std::mem::size_of::<InfoOutput>() as u64,
+            READBACK_FRAMES,
+        );
+
+        let partial_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Follow Partial Buffer"),
+            size: MAX_REDUCE_WORKGROUPS * PARTIAL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let dispatch_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Follow Dispatch Buffer"),
+            size: std::mem::size_of::<wgpu::util::DispatchIndirectArgs>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
             mapped_at_creation: false,
         });
 
+        let param_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Follow Param Buffer"),
+            size: std::mem::size_of::<FollowParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Follow Histogram Buffer"),
+            size: HISTOGRAM_BINS as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let distribution_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Follow Distribution Buffer"),
+            size: DISTRIBUTIONS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let distribution_readback = Readback::new(
+            device,
+            "Follow Distribution Staging Buffer",
+            DISTRIBUTIONS_SIZE + HISTOGRAM_BINS as u64 * 4,
+            READBACK_FRAMES,
+        );
+
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[
@@ -72,131 +273,373 @@ impl FollowModule {
                     },
                     count: None,
                 },
-            ],
-        });
-
-        let bind_group_a = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &particle_buffers[0],
-                        offset: 0,
-                        size: None,
-                    }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &position_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-            ],
-        });
-        let bind_group_b = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &particle_buffers[1],
-                        offset: 0,
-                        size: None,
-                    }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &position_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_groups = create_bind_groups(
+            device,
+            &bind_group_layout,
+            particle_buffers,
+            allocator_buffer,
+            [
+                &position_buffer,
+                &partial_buffer,
+                &param_buffer,
+                &histogram_buffer,
+                &distribution_buffer,
             ],
+        );
+
+        let dispatch_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let dispatch_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &dispatch_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: dispatch_buffer.as_entire_binding(),
+            }],
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &dispatch_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
+        // The dispatch buffer can't be bound while it's used for `dispatch_workgroups_indirect`
+        let reduce_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let prepare_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Follow Prepare Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &follow_shader,
+            entry_point: "prepare",
+        });
+        let reduce_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Follow Reduce Pipeline"),
+            layout: Some(&reduce_pipeline_layout),
+            module: &follow_shader,
+            entry_point: "reduce",
+        });
+        let finish_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Follow Finish Pipeline"),
             layout: Some(&pipeline_layout),
             module: &follow_shader,
-            entry_point: "main",
+            entry_point: "finish",
+        });
+        let bin_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Follow Bin Pipeline"),
+            layout: Some(&reduce_pipeline_layout),
+            module: &follow_shader,
+            entry_point: "bin_masses",
         });
+        let percentile_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Follow Percentile Pipeline"),
+                layout: Some(&reduce_pipeline_layout),
+                module: &follow_shader,
+                entry_point: "percentile",
+            });
+        let distribution_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Follow Distribution Pipeline"),
+                layout: Some(&reduce_pipeline_layout),
+                module: &follow_shader,
+                entry_point: "bin_distributions",
+            });
 
         Self {
             enabled: false,
-            center_of_mass: true,
+            target: Some(FollowTarget::CenterOfMass),
             auto_zoom: false,
+            zoom_percentile: 0.9,
+            zoom_margin: 0.2,
+            distributions: false,
+            log_bins: true,
 
             info: InfoOutput::default(),
 
             position_buffer,
-            staging_buffer,
+            readback,
+            partial_buffer,
+            dispatch_buffer,
+            param_buffer,
+            histogram_buffer,
+            distribution_buffer,
+            distribution_readback,
 
-            bind_groups: [bind_group_a, bind_group_b],
-            pipeline,
+            bind_group_layout,
+            bind_groups,
+            dispatch_bind_group,
+            prepare_pipeline,
+            reduce_pipeline,
+            finish_pipeline,
+            bin_pipeline,
+            percentile_pipeline,
+            distribution_pipeline,
         }
     }
 
+    pub fn resize_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer; 2],
+        allocator_buffer: &wgpu::Buffer,
+    ) {
+        self.bind_groups = create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            particle_buffers,
+            allocator_buffer,
+            [
+                &self.position_buffer,
+                &self.partial_buffer,
+                &self.param_buffer,
+                &self.histogram_buffer,
+                &self.distribution_buffer,
+            ],
+        );
+    }
+
+    /// Writes the id of the followed particle, the zoom percentile and the last followed
+    /// position, has to be called every frame before [`Self::begin_pass`]
+    pub fn update_params(&self, queue: &wgpu::Queue) {
+        let particle = match self.target {
+            Some(FollowTarget::Particle { id }) => id,
+            _ => 0,
+        };
+        let params = FollowParams {
+            particle,
+            percentile: self.zoom_percentile,
+            center: self
+                .target
+                .and_then(|target| target.position(&self.info))
+                .unwrap_or(self.info.center_of_mass),
+            log_bins: self.log_bins as u32,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.param_buffer, 0, bytemuck::bytes_of(&params));
+    }
+
     pub fn begin_pass<'a>(
         &'a self,
         encoder: &'a mut wgpu::CommandEncoder,
         particle_buffer_index: usize,
     ) {
-        if !self.enabled {
-            return;
-        }
-
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: None,
             timestamp_writes: None,
         });
 
-        cpass.set_pipeline(&self.pipeline);
         cpass.set_bind_group(0, &self.bind_groups[particle_buffer_index], &[]);
+        cpass.set_bind_group(1, &self.dispatch_bind_group, &[]);
+        cpass.set_pipeline(&self.prepare_pipeline);
+        cpass.dispatch_workgroups(1, 1, 1);
+        cpass.set_pipeline(&self.reduce_pipeline);
+        cpass.dispatch_workgroups_indirect(&self.dispatch_buffer, 0);
+        cpass.set_pipeline(&self.finish_pipeline);
         cpass.dispatch_workgroups(1, 1, 1);
+
+        // The radial bins are the surface density as well
+        if self.auto_zoom || self.distributions {
+            cpass.set_pipeline(&self.bin_pipeline);
+            cpass.dispatch_workgroups_indirect(&self.dispatch_buffer, 0);
+        }
+        if self.auto_zoom {
+            cpass.set_pipeline(&self.percentile_pipeline);
+            cpass.dispatch_workgroups(1, 1, 1);
+        }
+        if self.distributions {
+            cpass.set_pipeline(&self.distribution_pipeline);
+            cpass.dispatch_workgroups_indirect(&self.dispatch_buffer, 0);
+        }
     }
 
-    pub fn copy_buffer_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
-        if !self.enabled {
-            return;
+    /// Copies the statistics of `frame` to a staging buffer,
+    /// they are skipped when every staging buffer is still in flight
+    pub fn copy_buffer_to_buffer(&mut self, encoder: &mut wgpu::CommandEncoder, frame: u64) {
+        if let Some(staging_buffer) = self.readback.slot(frame) {
+            encoder.copy_buffer_to_buffer(
+                &self.position_buffer,
+                0,
+                staging_buffer,
+                0,
+                std::mem::size_of::<InfoOutput>() as u64,
+            );
         }
 
-        encoder.copy_buffer_to_buffer(
-            &self.position_buffer,
-            0,
-            &self.staging_buffer,
-            0,
-            std::mem::size_of::<InfoOutput>() as u64,
-        );
+        if !self.distributions {
+            return;
+        }
+        if let Some(staging_buffer) = self.distribution_readback.slot(frame) {
+            encoder.copy_buffer_to_buffer(
+                &self.distribution_buffer,
+                0,
+                staging_buffer,
+                0,
+                DISTRIBUTIONS_SIZE,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.histogram_buffer,
+                0,
+                staging_buffer,
+                DISTRIBUTIONS_SIZE,
+                HISTOGRAM_BINS as u64 * 4,
+            );
+        }
     }
 
-    pub fn get_data(&self, device: &wgpu::Device) -> Option<InfoOutput> {
-        self.enabled.then_some(())?;
+    /// Starts reading back the statistics copied this frame, has to be called after submitting them
+    pub fn map_readback(&mut self) {
+        self.readback.map_copies();
+        self.distribution_readback.map_copies();
+    }
 
-        let slice = self.staging_buffer.slice(..);
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        slice.map_async(wgpu::MapMode::Read, move |v| tx.send(v).unwrap());
+    /// The latest distributions that arrived and the frame they belong to,
+    /// without waiting for the GPU
+    pub fn get_distributions(&mut self, device: &wgpu::Device) -> Option<(u64, Distributions)> {
+        let mut latest = None;
+        self.distribution_readback.receive(device, |frame, data| {
+            latest = Some((frame, Distributions::from_bytes(data)));
+        });
+        latest
+    }
 
-        device.poll(wgpu::Maintain::wait()).panic_on_timeout();
-        if let Ok(Ok(())) = rx.recv() {
-            let data = slice.get_mapped_range();
-            let result: InfoOutput = bytemuck::cast_slice(&data)[0];
+    /// The latest statistics that arrived and the frame they belong to, without waiting for the GPU
+    pub fn get_data(&mut self, device: &wgpu::Device) -> Option<(u64, InfoOutput)> {
+        let mut latest = None;
+        self.readback.receive(device, |frame, data| {
+            latest = Some((frame, bytemuck::cast_slice(data)[0]));
+        });
+        latest
+    }
 
-            drop(data);
-            self.staging_buffer.unmap();
-            Some(result)
-        } else {
-            None
-        }
+    /// Like [`Self::get_data`], but waits for the statistics in flight
+    pub fn wait_data(&mut self, device: &wgpu::Device) -> Option<(u64, InfoOutput)> {
+        let mut latest = None;
+        self.readback.wait(device, |frame, data| {
+            latest = Some((frame, bytemuck::cast_slice(data)[0]));
+        });
+        latest
     }
 }
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    particle_buffers: &[wgpu::Buffer; 2],
+    allocator_buffer: &wgpu::Buffer,
+    [position_buffer, partial_buffer, param_buffer, histogram_buffer, distribution_buffer]: [&wgpu::Buffer;
+        5],
+) -> [wgpu::BindGroup; 2] {
+    particle_buffers.each_ref().map(|particle_buffer| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: particle_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: position_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: allocator_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: partial_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: param_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: distribution_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    })
+}
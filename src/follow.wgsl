@@ -6,11 +6,129 @@ var<storage, read> particles: array<Particle>;
 @binding(1)
 var<storage, read_write> output: Output;
 
+@group(0)
+@binding(2)
+var<storage, read> allocator: Allocator;
+
+@group(0)
+@binding(3)
+var<storage, read_write> partials: array<Partial>;
+
+@group(0)
+@binding(4)
+var<uniform> params: FollowParams;
+
+// Mass in every radial bin around `params.center`, in units of `MASS_SCALE` of the total mass
+@group(0)
+@binding(5)
+var<storage, read_write> histogram: array<atomic<u32>, HISTOGRAM_BINS>;
+
+// Particles in every speed and mass bin, see `bin_distributions`
+@group(0)
+@binding(6)
+var<storage, read_write> distributions: Distributions;
+
+@group(1)
+@binding(0)
+var<storage, read_write> dispatch: DispatchArgs;
+
 struct Output {
     center_of_mass: vec2<f32>,
     min_position: vec2<f32>,
     max_position: vec2<f32>,
+    // Total momentum over total mass
+    center_of_mass_velocity: vec2<f32>,
+    // Unweighted average position
+    centroid: vec2<f32>,
     avg_velocity: vec2<f32>,
+    // Centers of mass of the selected particles, of the particle `params.particle`
+    // with its fragments, the position of the heaviest particle and the center of mass
+    // of the particles flagged by `group.wgsl`
+    selection_center: vec2<f32>,
+    particle_position: vec2<f32>,
+    heaviest_position: vec2<f32>,
+    cluster_center: vec2<f32>,
+    total_mass: f32,
+    // `0` when there is no such particle
+    selection_mass: f32,
+    particle_mass: f32,
+    heaviest_mass: f32,
+    cluster_mass: f32,
+    // Radius around `params.center` containing `params.percentile` of the mass
+    percentile_radius: f32,
+    // Sum of `m v^2 / 2`
+    kinetic_energy: f32,
+    // Sum of `m (r x v)` around the origin
+    angular_momentum: f32,
+    // Sum of `r x v` around the origin
+    specific_angular_momentum: f32,
+    max_speed: f32,
+    // Particles with mass
+    count: u32,
+}
+
+struct FollowParams {
+    // Id of the followed particle
+    particle: u32,
+    percentile: f32,
+    // The position followed in the last frame
+    center: vec2<f32>,
+    // Space the speed and mass bins logarithmically instead of linearly
+    log_bins: u32,
+}
+
+// The statistics of a range of particles, combined pairwise into the final output
+struct Partial {
+    sum_position: vec2<f32>,
+    sum_velocity: vec2<f32>,
+    // Mass weighted sum of the positions
+    sum_moment: vec2<f32>,
+    sum_momentum: vec2<f32>,
+    min_position: vec2<f32>,
+    max_position: vec2<f32>,
+    selection_moment: vec2<f32>,
+    particle_moment: vec2<f32>,
+    heaviest_position: vec2<f32>,
+    cluster_moment: vec2<f32>,
+    sum_mass: f32,
+    selection_mass: f32,
+    particle_mass: f32,
+    heaviest_mass: f32,
+    cluster_mass: f32,
+    kinetic_energy: f32,
+    angular_momentum: f32,
+    specific_angular_momentum: f32,
+    max_speed: f32,
+    count: u32,
+}
+
+// The bins of `bin_distributions` with the ranges they were measured over
+struct Distributions {
+    max_speed: f32,
+    max_mass: f32,
+    // Of the radial bins in `histogram`
+    max_radius: f32,
+    total_mass: f32,
+    log_bins: u32,
+    speed: array<atomic<u32>, DISTRIBUTION_BINS>,
+    mass: array<atomic<u32>, DISTRIBUTION_BINS>,
+}
+
+// Indirect dispatch of `reduce`, `x` is also the number of partials
+struct DispatchArgs {
+    x: u32,
+    y: u32,
+    z: u32,
+}
+
+struct Allocator {
+    capacity: u32,
+    count: u32,
+    alive: u32,
+    free_count: u32,
+    compacted: u32,
+    spawn_count: u32,
+    next_id: u32,
 }
 
 struct Particle {
@@ -18,30 +136,328 @@ struct Particle {
     velocity: vec2<f32>,
     radius: f32,
     mass: f32,
+    // Low order part of `position`, only used with extended precision
+    position_lo: vec2<f32>,
+    level: u32,
+    time: u32,
+    color: u32,
+    // Index when uploaded, spawned particles count up from there and a shattered particle keeps
+    // it as its first fragment. The top bit marks selected particles and the one below
+    // the particles of the followed cluster
+    id: u32,
+}
+
+// Small enough for `shared_partials` to fit in 16 KiB of workgroup memory
+const WORKGROUP_SIZE: u32 = 128;
+// `finish` combines the partial of every workgroup with one invocation each
+const MAX_REDUCE_WORKGROUPS: u32 = WORKGROUP_SIZE;
+// Particles summed by an invocation before the workgroup is reduced, unless there are
+// so many that all workgroups are needed
+const PARTICLES_PER_INVOCATION: u32 = 16;
+const F32_MAX: f32 = 3.40282347e38;
+// The bins are spaced logarithmically over `2^-HISTOGRAM_OCTAVES` to `1` times the largest
+// possible radius, each bin is about 5% wider than the one before
+const HISTOGRAM_BINS: u32 = 256;
+const HISTOGRAM_OCTAVES: f32 = 20.0;
+// Bins of the speed and mass distributions, which span the same octaves when spaced logarithmically
+const DISTRIBUTION_BINS: u32 = 64u;
+// Masses are summed as fixed point fractions of the total mass so they can be added atomically
+const MASS_SCALE: f32 = 1073741824.0;
+const SELECTED: u32 = 0x80000000u;
+const CLUSTER: u32 = 0x40000000u;
+const ID_MASK: u32 = 0x3fffffffu;
+
+var<workgroup> shared_partials: array<Partial, WORKGROUP_SIZE>;
+var<workgroup> shared_histogram: array<atomic<u32>, HISTOGRAM_BINS>;
+
+fn empty_partial() -> Partial {
+    var partial: Partial;
+    partial.min_position = vec2<f32>(F32_MAX);
+    partial.max_position = vec2<f32>(-F32_MAX);
+    return partial;
 }
 
+fn combine(a: Partial, b: Partial) -> Partial {
+    var result: Partial;
+    result.sum_position = a.sum_position + b.sum_position;
+    result.sum_velocity = a.sum_velocity + b.sum_velocity;
+    result.sum_moment = a.sum_moment + b.sum_moment;
+    result.sum_momentum = a.sum_momentum + b.sum_momentum;
+    result.min_position = min(a.min_position, b.min_position);
+    result.max_position = max(a.max_position, b.max_position);
+    result.selection_moment = a.selection_moment + b.selection_moment;
+    result.particle_moment = a.particle_moment + b.particle_moment;
+    result.cluster_moment = a.cluster_moment + b.cluster_moment;
+    result.sum_mass = a.sum_mass + b.sum_mass;
+    result.selection_mass = a.selection_mass + b.selection_mass;
+    result.particle_mass = a.particle_mass + b.particle_mass;
+    result.cluster_mass = a.cluster_mass + b.cluster_mass;
+    result.kinetic_energy = a.kinetic_energy + b.kinetic_energy;
+    result.angular_momentum = a.angular_momentum + b.angular_momentum;
+    result.specific_angular_momentum = a.specific_angular_momentum + b.specific_angular_momentum;
+    result.max_speed = max(a.max_speed, b.max_speed);
+    result.count = a.count + b.count;
+
+    let a_heavier = a.heaviest_mass >= b.heaviest_mass;
+    result.heaviest_position = select(b.heaviest_position, a.heaviest_position, a_heavier);
+    result.heaviest_mass = max(a.heaviest_mass, b.heaviest_mass);
+    return result;
+}
 
+// Tree reduction of the partials of every invocation in the workgroup
+fn reduce_workgroup(local_index: u32, partial: Partial) -> Partial {
+    shared_partials[local_index] = partial;
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if local_index < stride {
+            shared_partials[local_index] = combine(shared_partials[local_index], shared_partials[local_index + stride]);
+        }
+        workgroupBarrier();
+    }
+
+    return shared_partials[0];
+}
+
+// Dispatches just enough workgroups for the particles in use
 @compute
 @workgroup_size(1)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    output.min_position = particles[0].position;
-    output.max_position = output.min_position;
-    
-    var particle_count = 0;
-    for (var i = 0u; i < arrayLength(&particles); i++) {
+fn prepare() {
+    let count = min(allocator.count, arrayLength(&particles));
+    let per_workgroup = WORKGROUP_SIZE * PARTICLES_PER_INVOCATION;
+    dispatch.x = clamp((count + per_workgroup - 1u) / per_workgroup, 1u, MAX_REDUCE_WORKGROUPS);
+    dispatch.y = 1u;
+    dispatch.z = 1u;
+
+    for (var bin = 0u; bin < HISTOGRAM_BINS; bin++) {
+        atomicStore(&histogram[bin], 0u);
+    }
+    for (var bin = 0u; bin < DISTRIBUTION_BINS; bin++) {
+        atomicStore(&distributions.speed[bin], 0u);
+        atomicStore(&distributions.mass[bin], 0u);
+    }
+}
+
+// Every invocation sums a strided range of particles, each workgroup writes one partial
+@compute
+@workgroup_size(128)
+fn reduce(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    var partial = empty_partial();
+    let count = min(allocator.count, arrayLength(&particles));
+    for (var i = global_id.x; i < count; i += num_workgroups.x * WORKGROUP_SIZE) {
         let particle = particles[i];
         if particle.mass == 0.0 {
             continue;
         }
-        
-        output.center_of_mass += particle.position;
-        output.avg_velocity += particle.velocity;
-        
-        output.min_position = min(output.min_position, particle.position);
-        output.max_position = max(output.max_position, particle.position);
-        particle_count += 1;
+
+        let position = particle.position + particle.position_lo;
+        partial.sum_position += position;
+        partial.sum_velocity += particle.velocity;
+        partial.sum_moment += position * particle.mass;
+        partial.sum_momentum += particle.velocity * particle.mass;
+        partial.sum_mass += particle.mass;
+        partial.kinetic_energy += 0.5 * particle.mass * dot(particle.velocity, particle.velocity);
+        let specific_angular_momentum = position.x * particle.velocity.y - position.y * particle.velocity.x;
+        partial.angular_momentum += particle.mass * specific_angular_momentum;
+        partial.specific_angular_momentum += specific_angular_momentum;
+        partial.max_speed = max(partial.max_speed, length(particle.velocity));
+        if (particle.id & SELECTED) != 0u {
+            partial.selection_moment += position * particle.mass;
+            partial.selection_mass += particle.mass;
+        }
+        if (particle.id & ID_MASK) == params.particle {
+            partial.particle_moment += position * particle.mass;
+            partial.particle_mass += particle.mass;
+        }
+        if (particle.id & CLUSTER) != 0u {
+            partial.cluster_moment += position * particle.mass;
+            partial.cluster_mass += particle.mass;
+        }
+        if particle.mass > partial.heaviest_mass {
+            partial.heaviest_position = position;
+            partial.heaviest_mass = particle.mass;
+        }
+        partial.min_position = min(partial.min_position, position);
+        partial.max_position = max(partial.max_position, position);
+        partial.count += 1u;
+    }
+
+    let result = reduce_workgroup(local_index, partial);
+    if local_index == 0u {
+        partials[workgroup_id.x] = result;
     }
+}
 
-    output.center_of_mass /= f32(particle_count);
-    output.avg_velocity /= f32(particle_count);
+// Combines the partials of `reduce`, the output is kept when there are no particles
+@compute
+@workgroup_size(128)
+fn finish(@builtin(local_invocation_index) local_index: u32) {
+    var partial = empty_partial();
+    if local_index < dispatch.x {
+        partial = partials[local_index];
+    }
+
+    let result = reduce_workgroup(local_index, partial);
+    if local_index != 0u || result.count == 0u {
+        return;
+    }
+
+    output.center_of_mass = result.sum_moment / result.sum_mass;
+    output.min_position = result.min_position;
+    output.max_position = result.max_position;
+    output.center_of_mass_velocity = result.sum_momentum / result.sum_mass;
+    output.centroid = result.sum_position / f32(result.count);
+    output.avg_velocity = result.sum_velocity / f32(result.count);
+    output.total_mass = result.sum_mass;
+    output.kinetic_energy = result.kinetic_energy;
+    output.angular_momentum = result.angular_momentum;
+    output.specific_angular_momentum = result.specific_angular_momentum;
+    output.max_speed = result.max_speed;
+    output.count = result.count;
+
+    output.selection_center = select(vec2<f32>(0.0), result.selection_moment / result.selection_mass, result.selection_mass > 0.0);
+    output.particle_position = select(vec2<f32>(0.0), result.particle_moment / result.particle_mass, result.particle_mass > 0.0);
+    output.heaviest_position = result.heaviest_position;
+    output.cluster_center = select(vec2<f32>(0.0), result.cluster_moment / result.cluster_mass, result.cluster_mass > 0.0);
+    output.selection_mass = result.selection_mass;
+    output.particle_mass = result.particle_mass;
+    output.heaviest_mass = result.heaviest_mass;
+    output.cluster_mass = result.cluster_mass;
+}
+
+// Upper bound of the distance of every particle to `params.center`, from the bounds of `finish`
+fn max_radius() -> f32 {
+    return length(max(abs(output.min_position - params.center), abs(output.max_position - params.center)));
+}
+
+// The radius at which `bin` starts
+fn bin_radius(bin: f32, radius: f32) -> f32 {
+    return radius * exp2((bin / f32(HISTOGRAM_BINS) - 1.0) * HISTOGRAM_OCTAVES);
+}
+
+// Sums the mass of every particle into its radial bin, first per workgroup
+@compute
+@workgroup_size(128)
+fn bin_masses(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    for (var bin = local_index; bin < HISTOGRAM_BINS; bin += WORKGROUP_SIZE) {
+        atomicStore(&shared_histogram[bin], 0u);
+    }
+    workgroupBarrier();
+
+    let count = min(allocator.count, arrayLength(&particles));
+    let radius = max_radius();
+    for (var i = global_id.x; i < count && radius > 0.0; i += num_workgroups.x * WORKGROUP_SIZE) {
+        let particle = particles[i];
+        if particle.mass == 0.0 {
+            continue;
+        }
+
+        let distance = max(length(particle.position + particle.position_lo - params.center), radius * 1e-7);
+        let position = (log2(distance / radius) / HISTOGRAM_OCTAVES + 1.0) * f32(HISTOGRAM_BINS);
+        let bin = u32(clamp(position, 0.0, f32(HISTOGRAM_BINS - 1u)));
+        atomicAdd(&shared_histogram[bin], u32(round(particle.mass / output.total_mass * MASS_SCALE)));
+    }
+    workgroupBarrier();
+
+    for (var bin = local_index; bin < HISTOGRAM_BINS; bin += WORKGROUP_SIZE) {
+        let mass = atomicLoad(&shared_histogram[bin]);
+        if mass != 0u {
+            atomicAdd(&histogram[bin], mass);
+        }
+    }
+}
+
+// Finds the bin the cumulative mass crosses `params.percentile` in and interpolates
+// the radius inside it
+@compute
+@workgroup_size(1)
+fn percentile() {
+    let radius = max_radius();
+    var total = 0.0;
+    for (var bin = 0u; bin < HISTOGRAM_BINS; bin++) {
+        total += f32(atomicLoad(&histogram[bin]));
+    }
+    if total == 0.0 {
+        output.percentile_radius = radius;
+        return;
+    }
+
+    let target_mass = params.percentile * total;
+    var cumulative = 0.0;
+    for (var bin = 0u; bin < HISTOGRAM_BINS; bin++) {
+        let mass = f32(atomicLoad(&histogram[bin]));
+        if cumulative + mass >= target_mass {
+            let fraction = select(1.0, (target_mass - cumulative) / mass, mass > 0.0);
+            output.percentile_radius = bin_radius(f32(bin) + fraction, radius);
+            return;
+        }
+        cumulative += mass;
+    }
+    output.percentile_radius = radius;
+}
+
+// The bin of `value` from `0` to `max_value`, the lowest logarithmic bin holds everything
+// below `2^-HISTOGRAM_OCTAVES` times `max_value`
+fn distribution_bin(value: f32, max_value: f32) -> u32 {
+    var position = value / max_value;
+    if params.log_bins != 0u {
+        position = log2(max(position, 1e-30)) / HISTOGRAM_OCTAVES + 1.0;
+    }
+    return u32(clamp(position * f32(DISTRIBUTION_BINS), 0.0, f32(DISTRIBUTION_BINS - 1u)));
+}
+
+// Counts the particles in every speed and mass bin, first per workgroup in the two halves
+// of `shared_histogram`
+@compute
+@workgroup_size(128)
+fn bin_distributions(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    for (var bin = local_index; bin < 2u * DISTRIBUTION_BINS; bin += WORKGROUP_SIZE) {
+        atomicStore(&shared_histogram[bin], 0u);
+    }
+    if global_id.x == 0u {
+        distributions.max_speed = output.max_speed;
+        distributions.max_mass = output.heaviest_mass;
+        distributions.max_radius = max_radius();
+        distributions.total_mass = output.total_mass;
+        distributions.log_bins = params.log_bins;
+    }
+    workgroupBarrier();
+
+    let count = min(allocator.count, arrayLength(&particles));
+    for (var i = global_id.x; i < count; i += num_workgroups.x * WORKGROUP_SIZE) {
+        let particle = particles[i];
+        if particle.mass == 0.0 {
+            continue;
+        }
+
+        if output.max_speed > 0.0 {
+            atomicAdd(&shared_histogram[distribution_bin(length(particle.velocity), output.max_speed)], 1u);
+        }
+        atomicAdd(&shared_histogram[DISTRIBUTION_BINS + distribution_bin(particle.mass, output.heaviest_mass)], 1u);
+    }
+    workgroupBarrier();
+
+    for (var bin = local_index; bin < DISTRIBUTION_BINS; bin += WORKGROUP_SIZE) {
+        let speed = atomicLoad(&shared_histogram[bin]);
+        if speed != 0u {
+            atomicAdd(&distributions.speed[bin], speed);
+        }
+        let mass = atomicLoad(&shared_histogram[DISTRIBUTION_BINS + bin]);
+        if mass != 0u {
+            atomicAdd(&distributions.mass[bin], mass);
+        }
+    }
 }
@@ -0,0 +1,457 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use glam::DVec2;
+
+use crate::{
+    particle::{Particle, ID_MASK},
+    physics::{Allocator, Gravity, PhysicsModule, DISPATCH_INDIRECT_OFFSET},
+    readback::Readback,
+};
+
+/// Label of particles that aren't in a group with enough members
+pub const NO_GROUP: u32 = u32::MAX;
+
+/// A friends-of-friends group, see [`GroupModule`]
+#[derive(Clone, Copy)]
+pub struct Group {
+    /// The smallest id of its members, it stays the same while the group holds together
+    pub label: u32,
+    pub members: u32,
+    pub mass: f32,
+    pub center_of_mass: DVec2,
+    /// Mass weighted root mean square speed relative to the center of mass
+    pub velocity_dispersion: f32,
+    /// Kinetic energy relative to the center of mass over the magnitude of the virial `|W|`,
+    /// see [`Gravity::virial`]
+    pub energy_ratio: f32,
+}
+
+impl Group {
+    /// A group is bound when its kinetic energy is below `|W|`, so `K + W < 0`. With the
+    /// `1 / r` force nothing ever fully escapes, but a group with twice the kinetic energy of
+    /// virial equilibrium spreads out faster than it pulls itself back together.
+    pub fn is_bound(&self) -> bool {
+        self.energy_ratio < 1.0
+    }
+}
+
+/// The groups found in one frame
+pub struct GroupCatalog {
+    /// `(id, label)` of every living particle, [`NO_GROUP`] if it isn't in a group
+    pub labels: Vec<(u32, u32)>,
+    /// Every group sorted by descending mass
+    pub groups: Vec<Group>,
+}
+
+impl GroupCatalog {
+    /// Collects the statistics of every labelled group in double precision
+    pub fn new(particles: &[Particle], labels: &[u32], gravity: Gravity) -> Self {
+        #[derive(Default)]
+        struct Sums {
+            members: u32,
+            mass: f64,
+            mass_squared: f64,
+            mass_cubed: f64,
+            moment: DVec2,
+            momentum: DVec2,
+            /// `Σ m v²`
+            speed_moment: f64,
+        }
+
+        let mut sums = HashMap::<u32, Sums>::new();
+        let mut particle_labels = Vec::new();
+        for (particle, &label) in particles.iter().zip(labels) {
+            if particle.mass == 0.0 {
+                continue;
+            }
+            particle_labels.push((particle.id & ID_MASK, label));
+            if label == NO_GROUP {
+                continue;
+            }
+
+            let mass = particle.mass as f64;
+            let velocity = particle.velocity.as_dvec2();
+            let sum = sums.entry(label).or_default();
+            sum.members += 1;
+            sum.mass += mass;
+            sum.mass_squared += mass * mass;
+            sum.mass_cubed += mass * mass * mass;
+            sum.moment += particle.position() * mass;
+            sum.momentum += velocity * mass;
+            sum.speed_moment += velocity.length_squared() * mass;
+        }
+
+        let mut groups: Vec<_> = sums
+            .into_iter()
+            .map(|(label, sum)| {
+                let velocity = sum.momentum / sum.mass;
+                let speed_squared =
+                    (sum.speed_moment / sum.mass - velocity.length_squared()).max(0.0);
+                let kinetic_energy = 0.5 * sum.mass * speed_squared;
+                let virial = gravity.virial(sum.mass, sum.mass_squared, sum.mass_cubed);
+
+                Group {
+                    label,
+                    members: sum.members,
+                    mass: sum.mass as f32,
+                    center_of_mass: sum.moment / sum.mass,
+                    velocity_dispersion: speed_squared.sqrt() as f32,
+                    energy_ratio: (kinetic_energy / virial) as f32,
+                }
+            })
+            .collect();
+        groups.sort_by(|a, b| b.mass.total_cmp(&a.mass).then(a.label.cmp(&b.label)));
+
+        Self {
+            labels: particle_labels,
+            groups,
+        }
+    }
+
+    /// The heaviest group held together by its own gravity
+    pub fn largest_bound(&self) -> Option<&Group> {
+        self.groups.iter().find(|group| group.is_bound())
+    }
+}
+
+/// Finds groups of particles closer than the linking length to another member
+/// with a union-find over every pair, see `group.wgsl`
+pub struct GroupModule {
+    /// Particles closer than this belong to the same group
+    pub linking_length: f32,
+    /// Groups with fewer members aren't labelled
+    pub min_members: u32,
+    /// Label of the cluster whose particles are flagged for [`crate::follow::FollowTarget::LargestCluster`]
+    pub cluster: Option<u32>,
+    pub catalog: Option<GroupCatalog>,
+
+    param_buffer: wgpu::Buffer,
+    parent_buffer: wgpu::Buffer,
+    size_buffer: wgpu::Buffer,
+    min_id_buffer: wgpu::Buffer,
+    label_buffer: wgpu::Buffer,
+    /// The allocator, the particles and their labels of the frames whose catalog was requested
+    readback: Readback,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_groups: [wgpu::BindGroup; 2],
+    init_pipeline: wgpu::ComputePipeline,
+    link_pipeline: wgpu::ComputePipeline,
+    count_pipeline: wgpu::ComputePipeline,
+    label_pipeline: wgpu::ComputePipeline,
+}
+
+impl GroupModule {
+    pub fn new(
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer; 2],
+        allocator_buffer: &wgpu::Buffer,
+        capacity: usize,
+    ) -> Self {
+        let group_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("group.wgsl"))),
+        });
+
+        // [linking_length_squared, min_members, cluster, padding]
+        let param_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Group Param Buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let [parent_buffer, size_buffer, min_id_buffer, label_buffer] =
+            create_group_buffers(device, capacity);
+        let readback = create_readback(device, &particle_buffers[0], &label_buffer);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_entry(0, false),
+                storage_entry(1, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+            ],
+        });
+
+        let bind_groups = create_bind_groups(
+            device,
+            &bind_group_layout,
+            particle_buffers,
+            allocator_buffer,
+            &param_buffer,
+            [&parent_buffer, &size_buffer, &min_id_buffer, &label_buffer],
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let [init_pipeline, link_pipeline, count_pipeline, label_pipeline] =
+            ["init", "link", "count_members", "label"].map(|entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("Group Pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &group_shader,
+                    entry_point,
+                })
+            });
+
+        Self {
+            linking_length: 0.5,
+            min_members: 10,
+            cluster: None,
+            catalog: None,
+
+            param_buffer,
+            parent_buffer,
+            size_buffer,
+            min_id_buffer,
+            label_buffer,
+            readback,
+
+            bind_group_layout,
+            bind_groups,
+            init_pipeline,
+            link_pipeline,
+            count_pipeline,
+            label_pipeline,
+        }
+    }
+
+    pub fn resize_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer; 2],
+        allocator_buffer: &wgpu::Buffer,
+        capacity: usize,
+    ) {
+        [
+            self.parent_buffer,
+            self.size_buffer,
+            self.min_id_buffer,
+            self.label_buffer,
+        ] = create_group_buffers(device, capacity);
+        // Catalogs still in flight are dropped with the old staging buffers
+        self.readback = create_readback(device, &particle_buffers[0], &self.label_buffer);
+        self.bind_groups = create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            particle_buffers,
+            allocator_buffer,
+            &self.param_buffer,
+            [
+                &self.parent_buffer,
+                &self.size_buffer,
+                &self.min_id_buffer,
+                &self.label_buffer,
+            ],
+        );
+    }
+
+    /// The group label of every particle slot, only valid after [`Self::find`]
+    pub fn label_buffer(&self) -> &wgpu::Buffer {
+        &self.label_buffer
+    }
+
+    /// Writes the linking length, the minimum members and the flagged cluster,
+    /// has to be called when any of them changes
+    pub fn update_params(&self, queue: &wgpu::Queue) {
+        let params = [
+            (self.linking_length * self.linking_length).to_bits(),
+            self.min_members,
+            self.cluster.unwrap_or(NO_GROUP),
+        ];
+        queue.write_buffer(&self.param_buffer, 0, bytemuck::cast_slice(&params));
+    }
+
+    /// Labels the groups of the current particles and flags the particles of
+    /// [`Self::cluster`], linking compares every pair so it costs about as much as a step
+    pub fn find(&self, encoder: &mut wgpu::CommandEncoder, physics_module: &PhysicsModule) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+
+        cpass.set_bind_group(0, &self.bind_groups[physics_module.current], &[]);
+        for pipeline in [
+            &self.init_pipeline,
+            &self.link_pipeline,
+            &self.count_pipeline,
+            &self.label_pipeline,
+        ] {
+            cpass.set_pipeline(pipeline);
+            cpass.dispatch_workgroups_indirect(
+                &physics_module.indirect_buffer,
+                DISPATCH_INDIRECT_OFFSET,
+            );
+        }
+    }
+
+    /// Copies the particles and their labels found by [`Self::find`] earlier in `encoder`
+    /// to be read back, returns `false` when all staging buffers are still in flight
+    pub fn copy_catalog(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        physics_module: &PhysicsModule,
+        frame: u64,
+    ) -> bool {
+        let Some(staging_buffer) = self.readback.slot(frame) else {
+            return false;
+        };
+
+        let allocator_size = std::mem::size_of::<Allocator>() as u64;
+        let particles_size = physics_module.current_buffer().size();
+        encoder.copy_buffer_to_buffer(
+            &physics_module.allocator_buffer,
+            0,
+            staging_buffer,
+            0,
+            allocator_size,
+        );
+        encoder.copy_buffer_to_buffer(
+            physics_module.current_buffer(),
+            0,
+            staging_buffer,
+            allocator_size,
+            particles_size,
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.label_buffer,
+            0,
+            staging_buffer,
+            allocator_size + particles_size,
+            self.label_buffer.size(),
+        );
+        true
+    }
+
+    /// Has to be called after submitting [`Self::copy_catalog`]
+    pub fn map_readback(&mut self) {
+        self.readback.map_copies();
+    }
+
+    /// The catalog of the latest frame copied by [`Self::copy_catalog`] that arrived,
+    /// without waiting for the GPU
+    pub fn get_catalog(
+        &mut self,
+        device: &wgpu::Device,
+        physics_module: &PhysicsModule,
+        gravity: Gravity,
+    ) -> Option<GroupCatalog> {
+        let allocator_size = std::mem::size_of::<Allocator>();
+        let particles_size = physics_module.current_buffer().size() as usize;
+
+        let mut latest = None;
+        self.readback.receive(device, |_, data| {
+            let (allocator, buffers) = data.split_at(allocator_size);
+            let (particles, labels) = buffers.split_at(particles_size);
+            let allocator: Allocator = bytemuck::pod_read_unaligned(allocator);
+            let count = allocator.count.min(physics_module.capacity as u32) as usize;
+            latest = Some(GroupCatalog::new(
+                &bytemuck::cast_slice::<_, Particle>(particles)[..count],
+                &bytemuck::cast_slice::<_, u32>(labels)[..count],
+                gravity,
+            ));
+        });
+        latest
+    }
+}
+
+/// Staging buffers for the allocator, the particles and their labels
+fn create_readback(
+    device: &wgpu::Device,
+    particle_buffer: &wgpu::Buffer,
+    label_buffer: &wgpu::Buffer,
+) -> Readback {
+    let size =
+        std::mem::size_of::<Allocator>() as u64 + particle_buffer.size() + label_buffer.size();
+    Readback::new(device, "Group Staging Buffer", size, 2)
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// The parent, size, min id and label buffers with one `u32` per particle
+fn create_group_buffers(device: &wgpu::Device, capacity: usize) -> [wgpu::Buffer; 4] {
+    [
+        "Group Parent Buffer",
+        "Group Size Buffer",
+        "Group Min Id Buffer",
+        "Group Label Buffer",
+    ]
+    .map(|label| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity.max(1) * std::mem::size_of::<u32>()) as u64,
+            // The labels are also an instance buffer of the renderer
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    })
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    particle_buffers: &[wgpu::Buffer; 2],
+    allocator_buffer: &wgpu::Buffer,
+    param_buffer: &wgpu::Buffer,
+    group_buffers: [&wgpu::Buffer; 4],
+) -> [wgpu::BindGroup; 2] {
+    particle_buffers.each_ref().map(|particle_buffer| {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: particle_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: allocator_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: param_buffer.as_entire_binding(),
+            },
+        ];
+        entries.extend(
+            (3..)
+                .zip(group_buffers)
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding,
+                    resource: buffer.as_entire_binding(),
+                }),
+        );
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: bind_group_layout,
+            entries: &entries,
+        })
+    })
+}
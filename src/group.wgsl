@@ -0,0 +1,182 @@
+@group(0)
+@binding(0)
+var<storage, read_write> particles: array<Particle>;
+
+@group(0)
+@binding(1)
+var<storage, read> allocator: Allocator;
+
+@group(0)
+@binding(2)
+var<uniform> params: GroupParams;
+
+// Union-find forest over the particle slots, every parent has a lower index than its children
+@group(0)
+@binding(3)
+var<storage, read_write> parents: array<atomic<u32>>;
+
+// Members and smallest member id of every root
+@group(0)
+@binding(4)
+var<storage, read_write> sizes: array<atomic<u32>>;
+
+@group(0)
+@binding(5)
+var<storage, read_write> min_ids: array<atomic<u32>>;
+
+@group(0)
+@binding(6)
+var<storage, read_write> labels: array<u32>;
+
+struct GroupParams {
+    linking_length_squared: f32,
+    // Groups with fewer members are labelled `NO_GROUP`
+    min_members: u32,
+    // Label of the group whose particles get the `CLUSTER` flag, `NO_GROUP` clears it
+    cluster: u32,
+}
+
+struct Allocator {
+    capacity: u32,
+    count: u32,
+    alive: u32,
+    free_count: u32,
+    compacted: u32,
+    spawn_count: u32,
+    next_id: u32,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    radius: f32,
+    mass: f32,
+    // Low order part of `position`, only used with extended precision
+    position_lo: vec2<f32>,
+    level: u32,
+    time: u32,
+    color: u32,
+    // Index when uploaded, spawned particles count up from there and a shattered particle keeps
+    // it as its first fragment. The top bit marks selected particles and the one below
+    // the particles of the followed cluster
+    id: u32,
+}
+
+const CLUSTER: u32 = 0x40000000u;
+const ID_MASK: u32 = 0x3fffffffu;
+const NO_GROUP: u32 = 0xffffffffu;
+
+fn particle_count() -> u32 {
+    return min(allocator.count, arrayLength(&particles));
+}
+
+fn find(index: u32) -> u32 {
+    var root = index;
+    loop {
+        let parent = atomicLoad(&parents[root]);
+        if parent == root {
+            return root;
+        }
+        root = parent;
+    }
+    return root;
+}
+
+// Hooks the higher of both roots under the lower one. When another invocation hooked the
+// higher root first its new parent is joined with the lower root instead, parents only
+// ever decrease so this ends once both are in the same tree
+fn join(a: u32, b: u32) {
+    var x = a;
+    var y = b;
+    loop {
+        x = find(x);
+        y = find(y);
+        if x == y {
+            return;
+        }
+
+        let low = min(x, y);
+        let high = max(x, y);
+        let previous = atomicMin(&parents[high], low);
+        if previous == high {
+            return;
+        }
+        x = low;
+        y = previous;
+    }
+}
+
+@compute
+@workgroup_size(256)
+fn init(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= particle_count() {
+        return;
+    }
+
+    atomicStore(&parents[index], index);
+    atomicStore(&sizes[index], 0u);
+    atomicStore(&min_ids[index], NO_GROUP);
+}
+
+// Joins every pair of living particles closer than the linking length
+@compute
+@workgroup_size(256)
+fn link(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    let count = particle_count();
+    if index >= count || particles[index].mass == 0.0 {
+        return;
+    }
+
+    let particle = particles[index];
+    for (var other = index + 1u; other < count; other++) {
+        let neighbour = particles[other];
+        if neighbour.mass == 0.0 {
+            continue;
+        }
+
+        let offset = (neighbour.position - particle.position) + (neighbour.position_lo - particle.position_lo);
+        if dot(offset, offset) <= params.linking_length_squared {
+            join(index, other);
+        }
+    }
+}
+
+// Points every particle straight at its root and counts the members of each group
+@compute
+@workgroup_size(256)
+fn count_members(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= particle_count() || particles[index].mass == 0.0 {
+        return;
+    }
+
+    let root = find(index);
+    atomicStore(&parents[index], root);
+    atomicAdd(&sizes[root], 1u);
+    atomicMin(&min_ids[root], particles[index].id & ID_MASK);
+}
+
+// Labels groups by their smallest member id, ids are unique and don't change when particles are
+// compacted, and flags the particles of `params.cluster`
+@compute
+@workgroup_size(256)
+fn label(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= particle_count() {
+        return;
+    }
+
+    var label = NO_GROUP;
+    if particles[index].mass != 0.0 {
+        let root = atomicLoad(&parents[index]);
+        if atomicLoad(&sizes[root]) >= params.min_members {
+            label = atomicLoad(&min_ids[root]);
+        }
+    }
+    labels[index] = label;
+
+    let in_cluster = label != NO_GROUP && label == params.cluster;
+    particles[index].id = select(particles[index].id & ~CLUSTER, particles[index].id | CLUSTER, in_cluster);
+}
@@ -0,0 +1,139 @@
+//! The speed, mass and surface density distributions measured by `follow.wgsl`
+
+use std::{f32::consts::PI, fmt::Write as _, path::Path};
+
+use anyhow::Context;
+use egui_plot::{Line, Plot, PlotPoints};
+
+use crate::follow::{DISTRIBUTION_BINS, HISTOGRAM_BINS, HISTOGRAM_OCTAVES, MASS_SCALE};
+
+/// Size of the header of `Distributions` in `follow.wgsl` in `u32`s
+const HEADER_WORDS: usize = 5;
+/// Size of `Distributions` in `follow.wgsl`
+pub const DISTRIBUTIONS_SIZE: u64 = ((HEADER_WORDS + 2 * DISTRIBUTION_BINS) * 4) as u64;
+
+pub struct Histogram {
+    /// The edges of the bins, one more than there are values
+    pub edges: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+impl Histogram {
+    /// Bins from `0` to `max`, the logarithmic ones are spaced like in `follow.wgsl`
+    /// with the lowest one reaching down to `0`
+    fn new(values: Vec<f32>, max: f32, log_bins: bool) -> Self {
+        let bins = values.len();
+        let edges = (0..=bins)
+            .map(|edge| {
+                let fraction = edge as f32 / bins as f32;
+                if !log_bins {
+                    max * fraction
+                } else if edge == 0 {
+                    0.0
+                } else {
+                    max * ((fraction - 1.0) * HISTOGRAM_OCTAVES).exp2()
+                }
+            })
+            .collect();
+
+        Self { edges, values }
+    }
+
+    /// The center of every bin with its value, on a logarithmic axis the geometric center
+    fn points(&self, log_x: bool, log_y: bool) -> PlotPoints {
+        self.edges
+            .windows(2)
+            .zip(&self.values)
+            .filter(|(edges, &value)| (!log_x || edges[0] > 0.0) && (!log_y || value > 0.0))
+            .map(|(edges, &value)| {
+                let x = if log_x {
+                    (edges[0] * edges[1]).sqrt().log10()
+                } else {
+                    (edges[0] + edges[1]) / 2.0
+                };
+                let y = if log_y { value.log10() } else { value };
+                [x as f64, y as f64]
+            })
+            .collect()
+    }
+
+    /// Plots the histogram, logarithmic axes show the powers of ten
+    pub fn plot(&self, ui: &mut egui::Ui, name: &str, log_x: bool, log_y: bool) {
+        let mut plot = Plot::new(name).height(120.0).x_axis_label(name);
+        if log_x {
+            plot = plot.x_axis_formatter(|mark, _, _| format!("{:.3e}", 10f64.powf(mark.value)));
+        }
+        if log_y {
+            plot = plot.y_axis_formatter(|mark, _, _| format!("{:.1e}", 10f64.powf(mark.value)));
+        }
+
+        plot.show(ui, |plot_ui| {
+            plot_ui.line(Line::new(self.points(log_x, log_y)).name(name));
+        });
+    }
+}
+
+pub struct Distributions {
+    /// The speed and mass bins are spaced logarithmically, the radial ones always are
+    pub log_bins: bool,
+    /// Particles in every speed bin
+    pub speed: Histogram,
+    /// Mass per area in every ring around the position the camera follows
+    pub surface_density: Histogram,
+    /// Particles in every mass bin
+    pub mass: Histogram,
+}
+
+impl Distributions {
+    /// Reads `Distributions` of `follow.wgsl` followed by its radial histogram
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let words: &[u32] = bytemuck::cast_slice(data);
+        let (header, bins) = words.split_at(HEADER_WORDS);
+        let [max_speed, max_mass, max_radius, total_mass] =
+            [0, 1, 2, 3].map(|i| f32::from_bits(header[i]));
+        let log_bins = header[4] != 0;
+
+        let (speed, bins) = bins.split_at(DISTRIBUTION_BINS);
+        let (mass, radial) = bins.split_at(DISTRIBUTION_BINS);
+        let counts = |bins: &[u32]| bins.iter().map(|&count| count as f32).collect();
+
+        let mut surface_density = Histogram::new(
+            radial[..HISTOGRAM_BINS]
+                .iter()
+                .map(|&mass| mass as f32 / MASS_SCALE * total_mass)
+                .collect(),
+            max_radius,
+            true,
+        );
+        for (value, edges) in surface_density
+            .values
+            .iter_mut()
+            .zip(surface_density.edges.windows(2))
+        {
+            *value /= PI * (edges[1] * edges[1] - edges[0] * edges[0]);
+        }
+
+        Self {
+            log_bins,
+            speed: Histogram::new(counts(speed), max_speed, log_bins),
+            surface_density,
+            mass: Histogram::new(counts(mass), max_mass, log_bins),
+        }
+    }
+
+    /// Writes every bin as `distribution,start,end,value`
+    pub fn write_csv(&self, path: &Path) -> anyhow::Result<()> {
+        let mut text = String::from("distribution,start,end,value\n");
+        for (name, histogram) in [
+            ("speed", &self.speed),
+            ("surface_density", &self.surface_density),
+            ("mass", &self.mass),
+        ] {
+            for (edges, value) in histogram.edges.windows(2).zip(&histogram.values) {
+                writeln!(text, "{name},{},{},{value}", edges[0], edges[1])?;
+            }
+        }
+
+        std::fs::write(path, text).with_context(|| format!("Failed to write `{}`", path.display()))
+    }
+}
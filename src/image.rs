@@ -0,0 +1,417 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::{bail, Context};
+use egui::Widget;
+use glam::Vec2;
+use rand::Rng;
+
+use crate::{
+    distribution::ParticleProperties,
+    particle::{Particle, ParticleSet},
+};
+
+/// An 8 bit RGBA image, rows go from top to bottom
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+/// Reads a PNG or a PPM/PGM image, the format is picked by the extension
+pub fn read_image(path: &Path) -> anyhow::Result<Image> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => read_png(path),
+        Some("ppm" | "pgm" | "pnm") => {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read `{}`", path.display()))?;
+            parse_pnm(&bytes)
+        }
+        _ => bail!("`{}` is not a PNG or PPM image", path.display()),
+    }
+    .with_context(|| format!("Failed to load `{}`", path.display()))
+}
+
+fn read_png(path: &Path) -> anyhow::Result<Image> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    // Palettes and bit depths other than 8 are converted to 8 bit channels
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => bytes.iter().map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks_exact(2)
+            .map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Rgb => bytes
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::Rgba => bytes
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect(),
+        png::ColorType::Indexed => bail!("indexed colors were not expanded"),
+    };
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+/// Parses binary (`P5`, `P6`) and plain (`P2`, `P3`) PGM and PPM images
+pub fn parse_pnm(bytes: &[u8]) -> anyhow::Result<Image> {
+    let mut position = 0;
+    let mut header = [0usize; 3];
+    let magic = next_token(bytes, &mut position).context("missing header")?;
+    let (channels, binary) = match magic {
+        b"P2" => (1, false),
+        b"P3" => (3, false),
+        b"P5" => (1, true),
+        b"P6" => (3, true),
+        _ => bail!("unsupported format, expected P2, P3, P5 or P6"),
+    };
+
+    for (value, name) in header.iter_mut().zip(["width", "height", "max value"]) {
+        let token = next_token(bytes, &mut position).with_context(|| format!("missing {name}"))?;
+        *value = std::str::from_utf8(token)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .with_context(|| format!("invalid {name}"))?;
+    }
+    let [width, height, max_value] = header;
+    if max_value == 0 || max_value > u16::MAX as usize {
+        bail!("max value must be between 1 and 65535");
+    }
+
+    let sample_size = if max_value > 255 { 2 } else { 1 };
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .filter(|count| count.checked_mul(sample_size).is_some())
+        .context("width and height are too large")?;
+    let samples: Vec<usize> = if binary {
+        // A single whitespace separates the header from the data
+        let data = bytes.get(position + 1..).unwrap_or_default();
+        if data.len() < count * sample_size {
+            bail!("expected {count} samples but the data ends early");
+        }
+
+        data.chunks_exact(sample_size)
+            .take(count)
+            .map(|sample| sample.iter().fold(0, |v, &b| v << 8 | b as usize))
+            .collect()
+    } else {
+        (0..count)
+            .map(|i| {
+                next_token(bytes, &mut position)
+                    .and_then(|token| std::str::from_utf8(token).ok()?.parse().ok())
+                    .with_context(|| format!("sample {} is missing or invalid", i + 1))
+            })
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    let scale = |sample: usize| (sample.min(max_value) * 255 / max_value) as u8;
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|p| match *p {
+            [v] => [scale(v), scale(v), scale(v), 255],
+            [r, g, b] => [scale(r), scale(g), scale(b), 255],
+            _ => unreachable!(),
+        })
+        .collect();
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// The next whitespace separated token, skipping `#` comments
+fn next_token<'a>(bytes: &'a [u8], position: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        while bytes.get(*position)?.is_ascii_whitespace() {
+            *position += 1;
+        }
+        if bytes[*position] != b'#' {
+            break;
+        }
+        while bytes.get(*position)? != &b'\n' {
+            *position += 1;
+        }
+    }
+
+    let start = *position;
+    while bytes
+        .get(*position)
+        .is_some_and(|byte| !byte.is_ascii_whitespace())
+    {
+        *position += 1;
+    }
+    Some(&bytes[start..*position])
+}
+
+/// The initial velocity of the particles spawned from an image, relative to its center
+#[derive(Clone, Copy, PartialEq, Default, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum VelocityField {
+    #[default]
+    Zero,
+    /// Spinning like a rigid body, counterclockwise when positive
+    Rotation { angular_velocity: f32 },
+    /// Flying apart with a speed proportional to the distance from the center
+    Explosion { expansion_rate: f32 },
+}
+
+impl VelocityField {
+    /// Every kind of field with default parameters
+    pub const DEFAULTS: [VelocityField; 3] = [
+        VelocityField::Zero,
+        VelocityField::Rotation {
+            angular_velocity: 0.1,
+        },
+        VelocityField::Explosion {
+            expansion_rate: 0.1,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VelocityField::Zero => "At Rest",
+            VelocityField::Rotation { .. } => "Rotation",
+            VelocityField::Explosion { .. } => "Explosion",
+        }
+    }
+
+    pub fn velocity(&self, position: Vec2) -> Vec2 {
+        match *self {
+            VelocityField::Zero => Vec2::ZERO,
+            VelocityField::Rotation { angular_velocity } => position.perp() * angular_velocity,
+            VelocityField::Explosion { expansion_rate } => position * expansion_rate,
+        }
+    }
+}
+
+/// The most particles a bright pixel can spawn
+const MAX_DENSITY: f32 = 16.0;
+
+/// Spawns particles on the bright pixels of an image
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageGenerator {
+    /// Pixels with a brightness (times alpha) from `0` to `1` above this spawn particles
+    pub threshold: f32,
+    /// The size of a pixel
+    pub scale: f32,
+    /// Particles per bright pixel up to [`MAX_DENSITY`], fractions spawn a particle
+    /// on some of the pixels
+    pub density: f32,
+    pub velocity: VelocityField,
+    /// Keep the color of the pixels instead of coloring by velocity
+    pub colors: bool,
+}
+
+impl Default for ImageGenerator {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            scale: 0.1,
+            density: 1.0,
+            velocity: VelocityField::Zero,
+            colors: true,
+        }
+    }
+}
+
+impl ImageGenerator {
+    /// Reads the image at `path` and generates its particles
+    pub fn load(
+        &self,
+        path: &Path,
+        properties: &ParticleProperties,
+    ) -> anyhow::Result<ParticleSet> {
+        if !(0.0..=MAX_DENSITY).contains(&self.density) {
+            bail!(
+                "the density must be between 0 and {MAX_DENSITY} but is {}",
+                self.density
+            );
+        }
+
+        let particles = self.generate(&read_image(path)?, properties);
+        if particles.is_empty() {
+            bail!(
+                "`{}` has no pixels brighter than the threshold {}",
+                path.display(),
+                self.threshold
+            );
+        }
+
+        Ok(particles)
+    }
+
+    /// Generates particles centered on the origin with masses and radii drawn from `properties`
+    pub fn generate(&self, image: &Image, properties: &ParticleProperties) -> ParticleSet {
+        let mut rng = rand::thread_rng();
+        let center = Vec2::new(image.width as f32, image.height as f32) / 2.0;
+        let mut particles = ParticleSet::new();
+
+        for (i, &[r, g, b, a]) in image.pixels.iter().enumerate() {
+            let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            if luminance * a as f32 / (255.0 * 255.0) <= self.threshold {
+                continue;
+            }
+
+            let pixel = Vec2::new((i % image.width) as f32, (i / image.width) as f32);
+            let count = self.density as usize + rng.gen_bool(self.density.fract() as f64) as usize;
+            for _ in 0..count {
+                let offset = Vec2::new(rng.gen(), rng.gen());
+                // Flipped so the image isn't upside down
+                let position = (pixel + offset - center) * Vec2::new(1.0, -1.0) * self.scale;
+                let mass = properties.sample_mass(&mut rng);
+
+                let mut particle = Particle::new(
+                    position.as_dvec2(),
+                    self.velocity.velocity(position),
+                    properties.radius(&mut rng, mass),
+                    mass,
+                );
+                if self.colors {
+                    particle.color = u32::from_le_bytes([r, g, b, 255]);
+                }
+                particles.push(particle);
+            }
+        }
+
+        particles
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Slider::new(&mut self.threshold, 0.0..=1.0)
+            .text("Threshold")
+            .ui(ui);
+        egui::DragValue::new(&mut self.scale)
+            .speed(0.01)
+            .clamp_range(0.001..=100.0)
+            .prefix("Pixel Size ")
+            .ui(ui);
+        egui::DragValue::new(&mut self.density)
+            .speed(0.01)
+            .clamp_range(0.0..=MAX_DENSITY)
+            .prefix("Particles per Pixel ")
+            .ui(ui);
+
+        egui::ComboBox::from_label("Velocity")
+            .selected_text(self.velocity.name())
+            .show_ui(ui, |ui| {
+                for option in VelocityField::DEFAULTS {
+                    let selected = option.name() == self.velocity.name();
+                    if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                        self.velocity = option;
+                    }
+                }
+            });
+
+        match &mut self.velocity {
+            VelocityField::Zero => {}
+            VelocityField::Rotation { angular_velocity } => {
+                egui::DragValue::new(angular_velocity)
+                    .speed(0.01)
+                    .prefix("Angular Velocity ")
+                    .ui(ui);
+            }
+            VelocityField::Explosion { expansion_rate } => {
+                egui::DragValue::new(expansion_rate)
+                    .speed(0.01)
+                    .prefix("Expansion Rate ")
+                    .ui(ui);
+            }
+        }
+
+        ui.checkbox(&mut self.colors, "Keep Colors");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(bytes: &[u8]) -> String {
+        format!("{:#}", parse_pnm(bytes).err().unwrap())
+    }
+
+    #[test]
+    fn plain_images() {
+        let image = parse_pnm(b"P2\n# a comment\n2 1\n4\n0 4\n").unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, [[0, 0, 0, 255], [255, 255, 255, 255]]);
+
+        let image = parse_pnm(b"P3 1 2 255  255 0 0  0 128 255").unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_eq!(image.pixels, [[255, 0, 0, 255], [0, 128, 255, 255]]);
+    }
+
+    #[test]
+    fn binary_images() {
+        let image = parse_pnm(b"P6 2 1 255\n\x01\x02\x03\x04\x05\x06").unwrap();
+        assert_eq!(image.pixels, [[1, 2, 3, 255], [4, 5, 6, 255]]);
+
+        // Two bytes per sample, most significant first
+        let image = parse_pnm(b"P5 2 1 65535\n\xff\xff\x80\x00").unwrap();
+        assert_eq!(image.pixels, [[255, 255, 255, 255], [127, 127, 127, 255]]);
+    }
+
+    #[test]
+    fn invalid_images() {
+        assert_eq!(error(b""), "missing header");
+        assert_eq!(
+            error(b"P4 1 1\n"),
+            "unsupported format, expected P2, P3, P5 or P6"
+        );
+        assert_eq!(error(b"P5 1"), "missing height");
+        assert_eq!(error(b"P5 1 -1 255"), "invalid height");
+        assert_eq!(
+            error(b"P5 1 1 0\n\0"),
+            "max value must be between 1 and 65535"
+        );
+        assert_eq!(
+            error(b"P6 2 1 255\n\x01\x02\x03"),
+            "expected 6 samples but the data ends early"
+        );
+        assert_eq!(error(b"P2 2 1 255\n7"), "sample 2 is missing or invalid");
+        assert_eq!(
+            error(format!("P6 {} 2 255\n", usize::MAX / 4).as_bytes()),
+            "width and height are too large"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_densities() {
+        let properties = ParticleProperties {
+            mass: "constant:1".parse().unwrap(),
+            radius: "constant:1".parse().unwrap(),
+        };
+
+        for density in [-1.0, f32::NAN, 1e9] {
+            let generator = ImageGenerator {
+                density,
+                ..Default::default()
+            };
+            let err = generator
+                .load(Path::new("image.png"), &properties)
+                .err()
+                .unwrap();
+            assert!(err.to_string().starts_with("the density must be"), "{err}");
+        }
+    }
+}
@@ -1,41 +1,74 @@
+mod benchmark;
+mod camera;
 mod cli;
+mod csv;
+mod distribution;
+mod emitter;
 mod follow;
 mod framepace;
 mod gpu;
+mod group;
 mod gui;
+mod histogram;
+mod image;
 mod particle;
 mod physics;
+mod plots;
+mod preset;
+mod readback;
 mod render;
+mod scene;
+mod select;
+mod stats;
 mod utils;
 
 #[cfg(feature = "capture")]
 mod capture;
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
+use anyhow::Context;
+use camera::{Camera, MAX_ZOOM, MIN_ZOOM, PIXELS_PER_UNIT};
 use capture::CaptureModule;
 use clap::Parser;
+use distribution::{ParticleProperties, Preview};
 use egui::Widget;
-use follow::FollowModule;
+use emitter::Emitter;
+use follow::{FollowModule, FollowTarget};
 use framepace::Framepacer;
-use glam::Vec2;
+use glam::{DVec2, Vec2};
 use gpu::GpuContext;
+use group::{GroupModule, NO_GROUP};
 use gui::EguiIntegration;
-use log::warn;
+use histogram::Distributions;
+use image::ImageGenerator;
+use log::{error, info, warn};
+use particle::ParticleSet;
+use plots::Plots;
+use preset::Preset;
+use scene::Scene;
+use select::{SelectMode, SelectModule, Selection};
+use stats::{Sample, StatsLog};
 use utils::{multiple_of, Exists};
 use winit::{
     application::ApplicationHandler,
     event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
     window::Window,
 };
 
-use crate::{physics::PhysicsModule, render::RenderModule};
+use crate::{
+    physics::{Gravity, PhysicsModule, PhysicsParams, MAX_FRAGMENTS, MAX_TIMESTEP_LEVEL},
+    render::RenderModule,
+};
 
 pub const WINDOW_TITLE: &str = "Particle Simulation";
 pub const PARTICLES_PER_WORKGROUP: u32 = 256;
 
+/// The number of groups listed in the groups window
+const LISTED_GROUPS: usize = 10;
+
 fn main() -> anyhow::Result<()> {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
@@ -44,6 +77,23 @@ fn main() -> anyhow::Result<()> {
 
     // Collect Arguments
     let args = cli::Args::parse();
+    if args.benchmark_follow {
+        return benchmark::follow();
+    }
+    let scene = args.scene.as_deref().map(Scene::load).transpose()?;
+    let properties = ParticleProperties {
+        mass: args.mass,
+        radius: args.radius,
+    };
+    let input = match (&args.input, &args.image) {
+        (Some(path), _) => Some(csv::read_particles(path)?),
+        (None, Some(path)) => Some(ImageGenerator::default().load(path, &properties)?),
+        (None, None) => None,
+    };
+    if let Some(input) = &input {
+        input.validate().context("Invalid input")?;
+    }
+    let particles = input.as_ref().map_or(args.particles, |p| p.len() as u32);
 
     // Setup Winit
     let event_loop = EventLoop::new().unwrap();
@@ -57,27 +107,83 @@ fn main() -> anyhow::Result<()> {
         sim: SimulationState {
             physics_module: Exists::None,
             follow_module: Exists::None,
+            select_module: Exists::None,
+            group_module: Exists::None,
 
             gravity: args.gravity,
-            particles: args.particles,
+            particles,
+            max_particles: args.max_particles,
+            emitters: Vec::new(),
+            preset: args.preset.into(),
+            properties,
+            extended_precision: args.extended_precision,
+            max_timestep_level: args.max_timestep_level,
+            timestep_accuracy: args.timestep_accuracy,
+            continuous_collisions: !args.discrete_collisions,
+            fragmentation_speed: args.fragmentation_speed,
+            fragment_count: args.fragment_count,
+            min_fragment_mass: args.min_fragment_mass,
+            ejecta_speed: args.ejecta_speed,
+            head_on: args.head_on,
+            input,
 
             edited_gravity: args.gravity,
-            edited_particles: args.particles,
+            edited_particles: particles,
+            edited_max_particles: args.max_particles,
+            edited_preset: args.preset.into(),
+            edited_properties: properties,
+            properties_preview: None,
+
+            csv_path: args
+                .input
+                .map_or("particles.csv".into(), |path| path.display().to_string()),
+            image_path: args
+                .image
+                .map_or("image.png".into(), |path| path.display().to_string()),
+            image_generator: ImageGenerator::default(),
+            scene_path: args
+                .scene
+                .map_or("scene.toml".into(), |path| path.display().to_string()),
+            file_error: None,
+            save_csv: false,
+            load: None,
+            select: None,
+            find_groups: false,
+            color_by_group: false,
+            refresh_groups: false,
+            group_refresh_interval: 60,
+            frames_since_groups: 0,
+            steps: 0,
+            time: 0.0,
+            distributions: None,
+            distributions_log_y: true,
+            distributions_path: "distributions.csv".into(),
         },
         framepace: Framepacer::new(),
 
         is_right_click_pressed: false,
+        modifiers: ModifiersState::empty(),
+        selection_start: None,
         mouse_position: Vec2::ZERO,
 
-        view_offset: Vec2::ZERO,
-        view_zoom: 1.0,
+        camera: Camera::new(DVec2::ZERO, 1.0),
+        stats_log: StatsLog::new(args.stats_log, args.stats_interval),
+        plots: Plots::new(),
 
         time_scale: args.time_scale,
         is_paused: true,
         step: false,
         framerate: args.framerate,
+        frame: 0,
     };
 
+    if let Some(scene) = &scene {
+        let particles = app_state.apply_scene(scene)?;
+        app_state.sim.particles = particles.len() as u32;
+        app_state.sim.edited_particles = particles.len() as u32;
+        app_state.sim.input = Some(particles);
+    }
+
     event_loop.run_app(&mut app_state)?;
     Ok(())
 }
@@ -94,12 +200,150 @@ struct GfxState {
 struct SimulationState {
     physics_module: Exists<PhysicsModule>,
     follow_module: Exists<FollowModule>,
+    select_module: Exists<SelectModule>,
+    group_module: Exists<GroupModule>,
 
     gravity: f32,
     particles: u32,
+    /// Room for emitted particles, the buffers fit at least this many
+    max_particles: u32,
+    emitters: Vec<Emitter>,
+    preset: Preset,
+    properties: ParticleProperties,
+    extended_precision: bool,
+    max_timestep_level: u32,
+    timestep_accuracy: f32,
+    continuous_collisions: bool,
+    /// Impact speed above which particles shatter, `0` disables fragmentation
+    fragmentation_speed: f32,
+    fragment_count: u32,
+    min_fragment_mass: f32,
+    ejecta_speed: f32,
+    /// Start with two particles colliding head-on at this speed
+    head_on: Option<f32>,
+    /// Particles loaded with `--input`, they replace the generated ones
+    input: Option<ParticleSet>,
 
     edited_gravity: f32,
     edited_particles: u32,
+    edited_max_particles: u32,
+    edited_preset: Preset,
+    edited_properties: ParticleProperties,
+    /// Histograms of `edited_properties`
+    properties_preview: Option<Preview>,
+
+    csv_path: String,
+    image_path: String,
+    image_generator: ImageGenerator,
+    scene_path: String,
+    file_error: Option<String>,
+    /// Save the particles to `csv_path` once the current frame is done
+    save_csv: bool,
+    /// Replace the particles once the current frame is done,
+    /// writing them earlier would get overwritten by the recorded step
+    load: Option<Load>,
+    /// Applied to the particles after the step of the current frame
+    select: Option<Selection>,
+    /// Read the groups back from the GPU once the next frame that finds them is done
+    find_groups: bool,
+    /// Finds the groups every frame to color the particles by them
+    color_by_group: bool,
+    /// Reads the groups back every `group_refresh_interval` frames
+    refresh_groups: bool,
+    group_refresh_interval: u32,
+    frames_since_groups: u32,
+    /// Steps taken since the start
+    steps: u64,
+    /// Simulated seconds since the start
+    time: f64,
+    /// The latest distributions measured by the follow module
+    distributions: Option<Distributions>,
+    distributions_log_y: bool,
+    distributions_path: String,
+}
+
+enum Load {
+    /// Generate `edited_particles` from `preset`
+    Generate,
+    /// Read `csv_path`
+    Csv,
+    /// Spawn particles on `image_path`
+    Image,
+    /// Reload `scene_path`
+    Scene,
+}
+
+impl SimulationState {
+    /// The size of the particle buffers for `particles`, leaving room up to `max_particles`
+    fn buffer_particles(&self, particles: u32) -> u32 {
+        multiple_of(particles.max(self.max_particles), PARTICLES_PER_WORKGROUP)
+    }
+
+    /// Resizes the particle buffers to fit `particles` if they don't already
+    fn resize_particles(&mut self, device: &wgpu::Device, particles: u32) {
+        let buffer_particles = self.buffer_particles(particles) as usize;
+        if buffer_particles != self.physics_module.capacity {
+            self.physics_module.resize_buffers(device, buffer_particles);
+            self.follow_module.resize_buffers(
+                device,
+                &self.physics_module.particle_buffers,
+                &self.physics_module.allocator_buffer,
+            );
+            self.select_module.resize_buffers(
+                device,
+                &self.physics_module.particle_buffers,
+                &self.physics_module.allocator_buffer,
+            );
+            self.group_module.resize_buffers(
+                device,
+                &self.physics_module.particle_buffers,
+                &self.physics_module.allocator_buffer,
+                buffer_particles,
+            );
+        }
+
+        self.particles = particles;
+        self.edited_particles = particles;
+    }
+
+    /// Replaces all particles with `particles`
+    fn load_particles(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles: &mut ParticleSet,
+    ) -> anyhow::Result<()> {
+        particles.validate()?;
+        self.resize_particles(device, particles.len() as u32);
+        particles.upload(queue, &mut self.physics_module);
+        Ok(())
+    }
+
+    /// Queues the particles of every emitter for the next step
+    fn emit(&mut self, queue: &wgpu::Queue, delta_time: f32) {
+        let mut particles = ParticleSet::new();
+        for emitter in &mut self.emitters {
+            emitter.emit(delta_time, &mut particles);
+        }
+
+        self.physics_module.emit(queue, &particles);
+    }
+
+    /// Writes every physics parameter except the delta time, which is updated each frame
+    fn update_physics_params(&mut self, queue: &wgpu::Queue) {
+        let module = &mut self.physics_module;
+        module.update_gravitational_constant(queue, self.gravity);
+        module.update_extended_precision(queue, self.extended_precision);
+        module.update_continuous_collisions(queue, self.continuous_collisions);
+        module.update_timestep_levels(queue, self.max_timestep_level, self.timestep_accuracy);
+        module.update_fragmentation(
+            queue,
+            self.fragmentation_speed,
+            self.fragment_count,
+            self.min_fragment_mass,
+            self.ejecta_speed,
+        );
+    }
 }
 
 struct AppState<'a> {
@@ -110,15 +354,109 @@ struct AppState<'a> {
     framepace: Framepacer,
 
     is_right_click_pressed: bool,
+    modifiers: ModifiersState,
+    /// Where the box being selected with shift and the left mouse button started
+    selection_start: Option<Vec2>,
     mouse_position: Vec2,
 
-    view_offset: Vec2,
-    view_zoom: f32,
+    camera: Camera,
+    stats_log: StatsLog,
+    plots: Plots,
 
     time_scale: f32,
     is_paused: bool,
     step: bool,
     framerate: u32,
+    /// Index of the current frame, data read back from the GPU is tagged with it
+    frame: u64,
+}
+
+impl<'a> AppState<'a> {
+    /// The world position under a point on the window, the inverse of `render.wgsl`
+    fn screen_to_world(&self, position: Vec2) -> DVec2 {
+        let size = self.gfx.window.inner_size();
+        let center = Vec2::new(size.width as f32, size.height as f32) / 2.0;
+        let relative =
+            (position - center) * Vec2::new(1.0, -1.0) / (PIXELS_PER_UNIT * self.camera.zoom);
+        relative.as_dvec2() - self.camera.offset
+    }
+
+    /// Builds the particles of `scene`, then takes over its physics and camera settings
+    fn apply_scene(&mut self, scene: &Scene) -> anyhow::Result<ParticleSet> {
+        let physics = &scene.physics;
+        let particles = scene.build(
+            Gravity {
+                gravitational_constant: physics.gravity.unwrap_or(self.sim.gravity),
+                delta_time: physics.time_scale.unwrap_or(self.time_scale),
+            },
+            &self.sim.properties,
+        )?;
+
+        let sim = &mut self.sim;
+        sim.gravity = physics.gravity.unwrap_or(sim.gravity);
+        sim.edited_gravity = sim.gravity;
+        self.time_scale = physics.time_scale.unwrap_or(self.time_scale);
+        sim.extended_precision = physics.extended_precision.unwrap_or(sim.extended_precision);
+        sim.max_timestep_level = physics
+            .max_timestep_level
+            .unwrap_or(sim.max_timestep_level)
+            .min(MAX_TIMESTEP_LEVEL);
+        sim.timestep_accuracy = physics.timestep_accuracy.unwrap_or(sim.timestep_accuracy);
+        sim.continuous_collisions = physics
+            .discrete_collisions
+            .map_or(sim.continuous_collisions, |discrete| !discrete);
+        sim.fragmentation_speed = physics
+            .fragmentation_speed
+            .unwrap_or(sim.fragmentation_speed);
+        sim.fragment_count = physics
+            .fragment_count
+            .unwrap_or(sim.fragment_count)
+            .clamp(2, MAX_FRAGMENTS);
+        sim.min_fragment_mass = physics.min_fragment_mass.unwrap_or(sim.min_fragment_mass);
+        sim.ejecta_speed = physics.ejecta_speed.unwrap_or(sim.ejecta_speed);
+        sim.max_particles = physics.max_particles.unwrap_or(sim.max_particles);
+        sim.edited_max_particles = sim.max_particles;
+        sim.emitters = scene.emitters.clone();
+
+        if let Some(center) = scene.camera.center {
+            self.camera.set_offset(-DVec2::from_array(center));
+        }
+        if let Some(zoom) = scene.camera.zoom {
+            self.camera.set_zoom(zoom);
+        }
+
+        Ok(particles)
+    }
+
+    /// Replaces the particles as requested by `self.sim.load`, returns the number loaded
+    fn load(&mut self, load: Load) -> anyhow::Result<usize> {
+        let mut particles = match load {
+            Load::Generate => self.sim.preset.generate(
+                self.sim.edited_particles as usize,
+                Gravity {
+                    gravitational_constant: self.sim.gravity,
+                    delta_time: self.time_scale,
+                },
+                &self.sim.properties,
+            ),
+            Load::Csv => csv::read_particles(Path::new(&self.sim.csv_path))?,
+            Load::Image => self
+                .sim
+                .image_generator
+                .load(Path::new(&self.sim.image_path), &self.sim.properties)?,
+            Load::Scene => {
+                let scene = Scene::load(Path::new(&self.sim.scene_path))?;
+                let particles = self.apply_scene(&scene)?;
+
+                self.sim.update_physics_params(&self.gpu.queue);
+                particles
+            }
+        };
+
+        self.sim
+            .load_particles(&self.gpu.device, &self.gpu.queue, &mut particles)?;
+        Ok(particles.len())
+    }
 }
 
 impl<'a> ApplicationHandler for AppState<'a> {
@@ -138,12 +476,42 @@ impl<'a> ApplicationHandler for AppState<'a> {
         let surface_capabilities = gpu.surface_capabilities();
         let surface_format = surface_capabilities.formats[0];
 
-        let buffer_particles = multiple_of(self.sim.particles, PARTICLES_PER_WORKGROUP);
+        let buffer_particles = self.sim.buffer_particles(self.sim.particles);
 
-        let physics_module =
-            PhysicsModule::new(&gpu.device, buffer_particles as usize, self.sim.gravity);
+        let mut physics_module = PhysicsModule::new(
+            &gpu.device,
+            buffer_particles as usize,
+            PhysicsParams {
+                delta_time: self.time_scale,
+                gravitational_constant: self.sim.gravity,
+                extended_precision: self.sim.extended_precision as u32,
+                max_timestep_level: self.sim.max_timestep_level,
+                timestep_accuracy: self.sim.timestep_accuracy,
+                continuous_collisions: self.sim.continuous_collisions as u32,
+                fragmentation_speed: self.sim.fragmentation_speed,
+                fragment_count: self.sim.fragment_count,
+                min_fragment_mass: self.sim.min_fragment_mass,
+                ejecta_speed: self.sim.ejecta_speed,
+            },
+        );
         let render_module = RenderModule::new(&gpu.device, surface_format);
-        let follow_module = FollowModule::new(&gpu.device, &physics_module.particle_buffers);
+        let follow_module = FollowModule::new(
+            &gpu.device,
+            &physics_module.particle_buffers,
+            &physics_module.allocator_buffer,
+        );
+        let select_module = SelectModule::new(
+            &gpu.device,
+            &physics_module.particle_buffers,
+            &physics_module.allocator_buffer,
+        );
+        let group_module = GroupModule::new(
+            &gpu.device,
+            &physics_module.particle_buffers,
+            &physics_module.allocator_buffer,
+            physics_module.capacity,
+        );
+        group_module.update_params(&gpu.queue);
 
         #[cfg(feature = "capture")]
         let capture_module = capture::CaptureModule::new(
@@ -153,15 +521,25 @@ impl<'a> ApplicationHandler for AppState<'a> {
             window_size.height,
         );
 
-        particle::generate_particles(&gpu.queue, &physics_module, self.sim.particles as u64);
-        render_module.update_all(
-            &gpu.queue,
-            window_size.width,
-            window_size.height,
-            0.0,
-            0.0,
-            1.0,
-        );
+        if let Some(mut particles) = self.sim.input.take() {
+            particles.upload(&gpu.queue, &mut physics_module);
+        } else if let Some(speed) = self.sim.head_on {
+            particle::head_on(speed).upload(&gpu.queue, &mut physics_module);
+        } else {
+            self.sim
+                .preset
+                .generate(
+                    self.sim.particles as usize,
+                    Gravity {
+                        gravitational_constant: self.sim.gravity,
+                        delta_time: self.time_scale,
+                    },
+                    &self.sim.properties,
+                )
+                .upload(&gpu.queue, &mut physics_module);
+        }
+        render_module.update_size(&gpu.queue, window_size.width, window_size.height);
+        self.camera.update(&gpu.queue, &render_module, 0.0);
 
         self.gfx = Exists::Some(GfxState {
             window,
@@ -173,6 +551,8 @@ impl<'a> ApplicationHandler for AppState<'a> {
         });
         self.sim.physics_module = Exists::Some(physics_module);
         self.sim.follow_module = Exists::Some(follow_module);
+        self.sim.select_module = Exists::Some(select_module);
+        self.sim.group_module = Exists::Some(group_module);
         self.gpu = Exists::Some(gpu);
     }
 
@@ -188,6 +568,9 @@ impl<'a> ApplicationHandler for AppState<'a> {
 
         match event {
             WindowEvent::CloseRequested => {
+                self.stats_log.close(&self.tokio_rt);
+                #[cfg(feature = "capture")]
+                self.gfx.capture_module.flush(&self.gpu.device);
                 event_loop.exit();
             }
 
@@ -214,6 +597,12 @@ impl<'a> ApplicationHandler for AppState<'a> {
                 );
             }
             WindowEvent::KeyboardInput { event, .. } => {
+                // Let text fields have every key
+                if self.gfx.egui.ctx.wants_keyboard_input() {
+                    self.gfx.egui.key_event(event);
+                    return;
+                }
+
                 let mut handled = true;
                 match (event.state, event.physical_key) {
                     (ElementState::Pressed, PhysicalKey::Code(KeyCode::Space)) => {
@@ -233,6 +622,10 @@ impl<'a> ApplicationHandler for AppState<'a> {
                         self.step = true;
                     }
 
+                    (ElementState::Pressed, PhysicalKey::Code(KeyCode::KeyS)) => {
+                        self.sim.save_csv = true;
+                    }
+
                     (ElementState::Pressed, PhysicalKey::Code(KeyCode::KeyF)) => {
                         self.sim.follow_module.enabled = !self.sim.follow_module.enabled;
                     }
@@ -250,6 +643,7 @@ impl<'a> ApplicationHandler for AppState<'a> {
                 }
             }
             WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
                 self.gfx.egui.modifiers_event(modifiers);
             }
             WindowEvent::MouseWheel { delta, .. } => {
@@ -257,16 +651,31 @@ impl<'a> ApplicationHandler for AppState<'a> {
                     MouseScrollDelta::LineDelta(_, y) => y,
                     MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
                 } * 0.005
-                    * self.view_zoom;
+                    * self.camera.zoom;
 
-                self.view_zoom = (self.view_zoom + delta).clamp(0.01, 10.0);
-                self.gfx
-                    .render_module
-                    .update_zoom(&self.gpu.queue, self.view_zoom);
+                self.camera.set_zoom(self.camera.zoom + delta);
             }
             WindowEvent::MouseInput { state, button, .. } => match (state, button) {
                 (ElementState::Pressed, MouseButton::Right) => self.is_right_click_pressed = true,
                 (ElementState::Released, MouseButton::Right) => self.is_right_click_pressed = false,
+                (ElementState::Pressed, MouseButton::Left)
+                    if self.modifiers.shift_key() && !self.gfx.egui.ctx.is_pointer_over_area() =>
+                {
+                    self.selection_start = Some(self.mouse_position);
+                }
+                (ElementState::Released, MouseButton::Left) if self.selection_start.is_some() => {
+                    let start = self.selection_start.take().unwrap();
+                    let mode = if self.modifiers.control_key() {
+                        SelectMode::Add
+                    } else {
+                        SelectMode::Replace
+                    };
+                    self.sim.select = Some(Selection::new(
+                        self.screen_to_world(start).as_vec2(),
+                        self.screen_to_world(self.mouse_position).as_vec2(),
+                        mode,
+                    ));
+                }
                 (state, button) => self
                     .gfx
                     .egui
@@ -274,14 +683,14 @@ impl<'a> ApplicationHandler for AppState<'a> {
             },
             WindowEvent::CursorMoved { position, .. } => {
                 let position = Vec2::new(position.x as f32, position.y as f32);
-                if self.is_right_click_pressed {
+                // The followed target keeps the camera in place
+                let following =
+                    self.sim.follow_module.enabled && self.sim.follow_module.target.is_some();
+                if self.is_right_click_pressed && !following {
                     let delta = position - self.mouse_position;
-                    self.view_offset += delta * Vec2::new(1.0, -1.0) * 0.005 / self.view_zoom;
-
-                    self.gfx.render_module.update_offset(
-                        &self.gpu.queue,
-                        self.view_offset.x,
-                        self.view_offset.y,
+                    self.camera.set_offset(
+                        self.camera.offset
+                            + (delta * Vec2::new(1.0, -1.0) * 0.005 / self.camera.zoom).as_dvec2(),
                     );
                 }
 
@@ -317,17 +726,63 @@ impl<'a> ApplicationHandler for AppState<'a> {
             .gpu
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.sim.physics_module.prepare(&mut encoder);
         if !self.is_paused || self.step {
-            let _cpass = self
-                .sim
-                .physics_module
-                .begin_pass(&mut encoder, self.sim.particles / PARTICLES_PER_WORKGROUP);
-
+            self.sim.emit(&self.gpu.queue, self.time_scale);
+            self.sim.physics_module.step(&mut encoder);
+            self.sim.steps += 1;
+            self.sim.time += self.time_scale as f64;
             self.step = false;
         }
+        if let Some(selection) = self.sim.select.take() {
+            self.sim.select_module.select(
+                &mut encoder,
+                &self.gpu.queue,
+                &self.sim.physics_module,
+                selection,
+            );
+        }
+
+        let follows_cluster = self.sim.follow_module.enabled
+            && self.sim.follow_module.target == Some(FollowTarget::LargestCluster);
+        self.sim.frames_since_groups += 1;
+        if (self.sim.refresh_groups || follows_cluster)
+            && self.sim.frames_since_groups >= self.sim.group_refresh_interval
+        {
+            self.sim.find_groups = true;
+        }
+        // Groups requested from the GUI are found in the next frame
+        let groups_found = self.sim.find_groups || self.sim.color_by_group || follows_cluster;
+        if groups_found {
+            let group_module = &mut self.sim.group_module;
+            group_module.find(&mut encoder, &self.sim.physics_module);
+            // Stays requested until a staging buffer is free, the catalog arrives a frame or two later
+            if self.sim.find_groups
+                && group_module.copy_catalog(&mut encoder, &self.sim.physics_module, self.frame)
+            {
+                self.sim.find_groups = false;
+                self.sim.frames_since_groups = 0;
+            }
+        }
 
         if let Exists::Some(gfx) = &mut self.gfx {
             gfx.egui.run(|ctx| {
+                if let Some(start) = self.selection_start {
+                    let rect = egui::Rect::from_two_pos(
+                        egui::pos2(start.x, start.y),
+                        egui::pos2(self.mouse_position.x, self.mouse_position.y),
+                    );
+                    ctx.layer_painter(egui::LayerId::new(
+                        egui::Order::Foreground,
+                        egui::Id::new("selection"),
+                    ))
+                    .rect_stroke(
+                        rect,
+                        0.0,
+                        egui::Stroke::new(1.0f32, egui::Color32::YELLOW),
+                    );
+                }
+
                 egui::Window::new("Settings")
                     .default_width(145.0)
                     .show(ctx, |ui| {
@@ -342,18 +797,106 @@ impl<'a> ApplicationHandler for AppState<'a> {
                 egui::Window::new("Simulation")
                     .default_width(145.0)
                     .show(ctx, |ui| {
+                        let info = &self.sim.follow_module.info;
                         ui.label(format!(
                             "Center of Mass\nx: {}\ny: {}",
-                            self.sim.follow_module.info.center_of_mass.x,
-                            self.sim.follow_module.info.center_of_mass.y,
+                            info.center_of_mass.x, info.center_of_mass.y,
                         ));
                         ui.add_space(5.0);
                         ui.label(format!(
-                            "Avg Velocity\nx: {}\ny: {}",
-                            self.sim.follow_module.info.avg_velocity.x,
-                            self.sim.follow_module.info.avg_velocity.y,
+                            "Center of Mass Velocity\nx: {}\ny: {}",
+                            info.center_of_mass_velocity.x, info.center_of_mass_velocity.y,
                         ));
                         ui.add_space(5.0);
+                        ui.label(format!(
+                            "Centroid\nx: {}\ny: {}",
+                            info.centroid.x, info.centroid.y,
+                        ));
+                        ui.add_space(5.0);
+                        ui.label(format!("Total Mass {}", info.total_mass));
+                        ui.add_space(5.0);
+
+                        if ui
+                            .checkbox(&mut self.sim.extended_precision, "Extended Precision")
+                            .changed()
+                        {
+                            self.sim.physics_module.update_extended_precision(
+                                &self.gpu.queue,
+                                self.sim.extended_precision,
+                            );
+                        }
+
+                        if ui
+                            .checkbox(&mut self.sim.continuous_collisions, "Continuous Collisions")
+                            .changed()
+                        {
+                            self.sim.physics_module.update_continuous_collisions(
+                                &self.gpu.queue,
+                                self.sim.continuous_collisions,
+                            );
+                        }
+
+                        let level_changed = ui
+                            .add(
+                                egui::Slider::new(
+                                    &mut self.sim.max_timestep_level,
+                                    0..=MAX_TIMESTEP_LEVEL,
+                                )
+                                .text("Timestep Levels"),
+                            )
+                            .changed();
+                        let accuracy_changed = ui
+                            .add(
+                                egui::DragValue::new(&mut self.sim.timestep_accuracy)
+                                    .speed(0.01)
+                                    .clamp_range(0.001..=10.0)
+                                    .prefix("Timestep Accuracy "),
+                            )
+                            .changed();
+                        if level_changed || accuracy_changed {
+                            self.sim.physics_module.update_timestep_levels(
+                                &self.gpu.queue,
+                                self.sim.max_timestep_level,
+                                self.sim.timestep_accuracy,
+                            );
+                        }
+
+                        ui.separator();
+                        let fragmentation_changed = [
+                            ui.add(
+                                egui::DragValue::new(&mut self.sim.fragmentation_speed)
+                                    .speed(0.1)
+                                    .clamp_range(0.0..=f32::MAX)
+                                    .prefix("Shatter above "),
+                            ),
+                            ui.add(
+                                egui::Slider::new(&mut self.sim.fragment_count, 2..=MAX_FRAGMENTS)
+                                    .text("Fragments"),
+                            ),
+                            ui.add(
+                                egui::DragValue::new(&mut self.sim.min_fragment_mass)
+                                    .speed(0.001)
+                                    .clamp_range(0.0..=f32::MAX)
+                                    .prefix("Min Fragment Mass "),
+                            ),
+                            ui.add(
+                                egui::DragValue::new(&mut self.sim.ejecta_speed)
+                                    .speed(0.01)
+                                    .clamp_range(0.0..=10.0)
+                                    .prefix("Ejecta Speed "),
+                            ),
+                        ]
+                        .iter()
+                        .any(|response| response.changed());
+                        if fragmentation_changed {
+                            self.sim.physics_module.update_fragmentation(
+                                &self.gpu.queue,
+                                self.sim.fragmentation_speed,
+                                self.sim.fragment_count,
+                                self.sim.min_fragment_mass,
+                                self.sim.ejecta_speed,
+                            );
+                        }
 
                         ui.separator();
                         egui::DragValue::new(&mut self.sim.edited_gravity)
@@ -362,27 +905,25 @@ impl<'a> ApplicationHandler for AppState<'a> {
                         egui::DragValue::new(&mut self.sim.edited_particles)
                             .suffix(" Particles")
                             .ui(ui);
+                        egui::DragValue::new(&mut self.sim.edited_max_particles)
+                            .prefix("Budget ")
+                            .suffix(" Particles")
+                            .ui(ui);
+                        self.sim.edited_preset.ui(ui);
+                        ui.collapsing("Mass and Radius", |ui| {
+                            self.sim
+                                .edited_properties
+                                .ui(ui, &mut self.sim.properties_preview);
+                        });
+                        egui::DragValue::new(&mut self.sim.physics_module.compaction_interval)
+                            .prefix("Compact every ")
+                            .suffix(" steps")
+                            .ui(ui);
 
                         if ui.button("Apply").clicked()
                             && self.sim.edited_particles > 0
                             && self.sim.edited_gravity > 0.0
                         {
-                            if self.sim.particles != self.sim.edited_particles {
-                                let buffer_particles =
-                                    multiple_of(self.sim.edited_particles, PARTICLES_PER_WORKGROUP);
-
-                                self.sim
-                                    .physics_module
-                                    .resize_buffers(&self.gpu.device, buffer_particles as usize);
-
-                                self.sim.particles = self.sim.edited_particles;
-                                particle::generate_particles(
-                                    &self.gpu.queue,
-                                    &self.sim.physics_module,
-                                    self.sim.particles as u64,
-                                );
-                            }
-
                             if self.sim.gravity != self.sim.edited_gravity {
                                 self.sim.gravity = self.sim.edited_gravity;
                                 self.sim.physics_module.update_gravitational_constant(
@@ -390,23 +931,311 @@ impl<'a> ApplicationHandler for AppState<'a> {
                                     self.sim.gravity,
                                 );
                             }
+
+                            if self.sim.particles != self.sim.edited_particles
+                                || self.sim.max_particles != self.sim.edited_max_particles
+                                || self.sim.preset != self.sim.edited_preset
+                                || self.sim.properties != self.sim.edited_properties
+                            {
+                                self.sim.max_particles = self.sim.edited_max_particles;
+                                self.sim.preset = self.sim.edited_preset.clone();
+                                self.sim.properties = self.sim.edited_properties;
+                                self.sim.load = Some(Load::Generate);
+                            }
+                        }
+
+                        ui.separator();
+                        ui.text_edit_singleline(&mut self.sim.csv_path);
+                        ui.horizontal(|ui| {
+                            if ui.button("Save CSV [s]").clicked() {
+                                self.sim.save_csv = true;
+                            }
+                            if ui.button("Load CSV").clicked() {
+                                self.sim.load = Some(Load::Csv);
+                            }
+                        });
+                        ui.text_edit_singleline(&mut self.sim.image_path);
+                        ui.collapsing("Image", |ui| self.sim.image_generator.ui(ui));
+                        if ui.button("Load Image").clicked() {
+                            self.sim.load = Some(Load::Image);
+                        }
+                        ui.text_edit_singleline(&mut self.sim.scene_path);
+                        if ui.button("Reload Scene").clicked() {
+                            self.sim.load = Some(Load::Scene);
+                        }
+                        if let Some(err) = &self.sim.file_error {
+                            ui.colored_label(egui::Color32::RED, err);
+                        }
+
+                        ui.separator();
+                        if ui
+                            .checkbox(&mut self.stats_log.enabled, "Log Statistics")
+                            .changed()
+                            && !self.stats_log.enabled
+                        {
+                            self.stats_log.close(&self.tokio_rt);
+                        }
+                        ui.text_edit_singleline(&mut self.stats_log.path);
+                        egui::DragValue::new(&mut self.stats_log.interval)
+                            .clamp_range(1..=u32::MAX)
+                            .suffix(" Steps per Row")
+                            .ui(ui);
+                        if let Some(err) = &self.stats_log.error {
+                            ui.colored_label(egui::Color32::RED, err);
+                        }
+                    });
+
+                egui::Window::new("Emitters")
+                    .default_width(145.0)
+                    .default_open(false)
+                    .show(ctx, |ui| {
+                        let mut removed = None;
+                        for (i, emitter) in self.sim.emitters.iter_mut().enumerate() {
+                            ui.push_id(i, |ui| {
+                                ui.collapsing(format!("Emitter {}", i + 1), |ui| {
+                                    emitter.ui(ui);
+                                    if ui.button("Remove").clicked() {
+                                        removed = Some(i);
+                                    }
+                                });
+                            });
+                        }
+                        if let Some(i) = removed {
+                            self.sim.emitters.remove(i);
+                        }
+
+                        if ui.button("Add at View Center").clicked() {
+                            self.sim.emitters.push(Emitter::at(-self.camera.offset));
+                        }
+                        if self.sim.max_particles <= self.sim.particles {
+                            ui.colored_label(
+                                egui::Color32::YELLOW,
+                                "Raise the particle budget to make room for emitted particles",
+                            );
+                        }
+                    });
+
+                egui::Window::new("Plots")
+                    .default_width(250.0)
+                    .default_open(false)
+                    .vscroll(true)
+                    .show(ctx, |ui| self.plots.ui(ui));
+
+                egui::Window::new("Distributions")
+                    .default_width(250.0)
+                    .default_open(false)
+                    .show(ctx, |ui| {
+                        let follow_module = &mut self.sim.follow_module;
+                        ui.checkbox(&mut follow_module.distributions, "Measure");
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut follow_module.log_bins, "Log X");
+                            ui.checkbox(&mut self.sim.distributions_log_y, "Log Y");
+                        });
+
+                        let Some(distributions) = &self.sim.distributions else {
+                            return;
+                        };
+                        let log_y = self.sim.distributions_log_y;
+                        ui.label("Particles by Speed");
+                        distributions
+                            .speed
+                            .plot(ui, "Speed", distributions.log_bins, log_y);
+                        ui.label("Surface Density around the Follow Center");
+                        distributions.surface_density.plot(
+                            ui,
+                            "Radius",
+                            distributions.log_bins,
+                            log_y,
+                        );
+                        ui.label("Particles by Mass");
+                        distributions
+                            .mass
+                            .plot(ui, "Mass", distributions.log_bins, log_y);
+
+                        ui.separator();
+                        ui.text_edit_singleline(&mut self.sim.distributions_path);
+                        if ui.button("Export CSV").clicked() {
+                            let path = Path::new(&self.sim.distributions_path);
+                            match distributions.write_csv(path) {
+                                Ok(()) => {
+                                    info!("Saved the distributions to `{}`", path.display());
+                                    self.sim.file_error = None;
+                                }
+                                Err(err) => {
+                                    error!("{err:#}");
+                                    self.sim.file_error = Some(format!("{err:#}"));
+                                }
+                            }
+                        }
+                        if let Some(err) = &self.sim.file_error {
+                            ui.colored_label(egui::Color32::RED, err);
+                        }
+                    });
+
+                egui::Window::new("Groups")
+                    .default_width(145.0)
+                    .default_open(false)
+                    .show(ctx, |ui| {
+                        let group_module = &mut self.sim.group_module;
+                        let params_changed = [
+                            ui.add(
+                                egui::DragValue::new(&mut group_module.linking_length)
+                                    .speed(0.01)
+                                    .clamp_range(0.0..=f32::MAX)
+                                    .prefix("Linking Length "),
+                            ),
+                            ui.add(
+                                egui::DragValue::new(&mut group_module.min_members)
+                                    .clamp_range(1..=u32::MAX)
+                                    .prefix("Min Members "),
+                            ),
+                        ]
+                        .iter()
+                        .any(|response| response.changed());
+                        if params_changed {
+                            group_module.update_params(&self.gpu.queue);
+                        }
+
+                        if ui
+                            .checkbox(&mut self.sim.color_by_group, "Color by Group")
+                            .changed()
+                        {
+                            gfx.render_module
+                                .update_color_by_group(&self.gpu.queue, self.sim.color_by_group);
+                        }
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.sim.refresh_groups, "Refresh every");
+                            egui::DragValue::new(&mut self.sim.group_refresh_interval)
+                                .clamp_range(1..=u32::MAX)
+                                .suffix(" frames")
+                                .ui(ui);
+                        });
+                        if ui.button("Find Groups").clicked() {
+                            self.sim.find_groups = true;
+                        }
+
+                        if let Some(catalog) = &group_module.catalog {
+                            let grouped = catalog
+                                .labels
+                                .iter()
+                                .filter(|(_, label)| *label != NO_GROUP)
+                                .count();
+                            ui.separator();
+                            ui.label(format!(
+                                "{} Groups\n{grouped} of {} particles grouped",
+                                catalog.groups.len(),
+                                catalog.labels.len(),
+                            ));
+                            egui::Grid::new("groups").striped(true).show(ui, |ui| {
+                                for header in
+                                    ["Label", "Members", "Mass", "Center", "Dispersion", "Bound"]
+                                {
+                                    ui.strong(header);
+                                }
+                                ui.end_row();
+
+                                for group in catalog.groups.iter().take(LISTED_GROUPS) {
+                                    ui.label(group.label.to_string());
+                                    ui.label(group.members.to_string());
+                                    ui.label(format!("{:.3}", group.mass));
+                                    ui.label(format!(
+                                        "{:.2}, {:.2}",
+                                        group.center_of_mass.x, group.center_of_mass.y,
+                                    ));
+                                    ui.label(format!("{:.3}", group.velocity_dispersion));
+                                    ui.label(if group.is_bound() { "Yes" } else { "No" });
+                                    ui.end_row();
+                                }
+                            });
                         }
                     });
 
                 egui::Window::new("View")
                     .default_width(145.0)
                     .show(ctx, |ui| {
+                        let camera = &mut self.camera;
                         ui.horizontal(|ui| {
                             ui.label("Zoom");
-                            egui::widgets::Slider::new(&mut self.view_zoom, 0.01..=10.0).ui(ui);
+                            let mut zoom = camera.zoom;
+                            if egui::widgets::Slider::new(&mut zoom, MIN_ZOOM..=MAX_ZOOM)
+                                .logarithmic(true)
+                                .ui(ui)
+                                .changed()
+                            {
+                                camera.set_zoom(zoom);
+                            }
+                        });
+                        ui.checkbox(&mut camera.smooth, "Smooth Camera");
+                        ui.add_enabled_ui(camera.smooth, |ui| {
+                            egui::DragValue::new(&mut camera.stiffness)
+                                .speed(0.1)
+                                .clamp_range(0.1..=100.0)
+                                .prefix("Stiffness ")
+                                .ui(ui);
                         });
+                        egui::DragValue::new(&mut camera.offset_dead_zone)
+                            .clamp_range(0.0..=f32::MAX)
+                            .prefix("Dead Zone ")
+                            .suffix(" px")
+                            .ui(ui);
+                        egui::DragValue::new(&mut camera.zoom_dead_zone)
+                            .speed(0.01)
+                            .clamp_range(0.0..=10.0)
+                            .prefix("Zoom Dead Zone ")
+                            .ui(ui);
 
                         ui.add_space(10.0);
                         ui.heading("Follow");
                         ui.separator();
                         ui.checkbox(&mut self.sim.follow_module.enabled, "Enabled [f]");
-                        ui.checkbox(&mut self.sim.follow_module.center_of_mass, "Center of Mass");
-                        ui.checkbox(&mut self.sim.follow_module.auto_zoom, "Auto Zoom");
+                        let follow_module = &mut self.sim.follow_module;
+                        let target = &mut follow_module.target;
+                        egui::ComboBox::from_label("Target")
+                            .selected_text(target.map_or("None", |target| target.name()))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(target, None, "None");
+                                for option in FollowTarget::DEFAULTS {
+                                    let selected =
+                                        target.is_some_and(|target| target.name() == option.name());
+                                    if ui.selectable_label(selected, option.name()).clicked()
+                                        && !selected
+                                    {
+                                        *target = Some(option);
+                                    }
+                                }
+                            });
+                        if let Some(FollowTarget::Particle { id }) = target {
+                            egui::DragValue::new(id)
+                                .clamp_range(0..=particle::ID_MASK)
+                                .prefix("Particle ")
+                                .ui(ui);
+                        }
+                        if follow_module.target == Some(FollowTarget::LargestCluster)
+                            && self.sim.group_module.cluster.is_none()
+                        {
+                            ui.label("No bound cluster found yet");
+                        }
+                        ui.checkbox(&mut follow_module.auto_zoom, "Auto Zoom");
+                        ui.add_enabled_ui(follow_module.auto_zoom, |ui| {
+                            ui.add(
+                                egui::Slider::new(&mut follow_module.zoom_percentile, 0.05..=1.0)
+                                    .text("of the Mass"),
+                            );
+                            egui::DragValue::new(&mut follow_module.zoom_margin)
+                                .speed(0.01)
+                                .clamp_range(0.0..=10.0)
+                                .prefix("Margin ")
+                                .ui(ui);
+                        });
+
+                        ui.add_space(10.0);
+                        ui.heading("Selection");
+                        ui.separator();
+                        ui.label("Shift + Drag to select\nCtrl + Shift + Drag to add");
+                        ui.label(format!("Mass {}", follow_module.info.selection_mass));
+                        if ui.button("Clear Selection").clicked() {
+                            self.sim.select = Some(Selection::clear());
+                        }
                     });
 
                 egui::Window::new("Capture")
@@ -444,7 +1273,8 @@ impl<'a> ApplicationHandler for AppState<'a> {
                     &mut encoder,
                     &view,
                     self.sim.physics_module.current_buffer(),
-                    self.sim.particles,
+                    self.sim.group_module.label_buffer(),
+                    &self.sim.physics_module.indirect_buffer,
                 );
 
                 gfx.egui.render(&mut rpass);
@@ -457,54 +1287,146 @@ impl<'a> ApplicationHandler for AppState<'a> {
                     &mut encoder,
                     &gfx.render_module,
                     self.sim.physics_module.current_buffer(),
-                    self.sim.particles,
+                    self.sim.group_module.label_buffer(),
+                    &self.sim.physics_module.indirect_buffer,
                 );
 
-                gfx.capture_module.copy_texture_to_buffer(&mut encoder);
+                gfx.capture_module.copy_texture_to_buffer(
+                    &self.gpu.device,
+                    &mut encoder,
+                    self.frame,
+                );
             }
         }
 
-        if self.sim.follow_module.enabled {
+        // The statistics are plotted, logged and the distributions measured without following as well
+        if self.sim.follow_module.enabled
+            || self.plots.collect
+            || self.stats_log.enabled
+            || self.sim.follow_module.distributions
+        {
+            self.stats_log.record(
+                self.frame,
+                Sample {
+                    step: self.sim.steps,
+                    time: self.sim.time,
+                },
+            );
+            self.sim.follow_module.update_params(&self.gpu.queue);
             self.sim
                 .follow_module
                 .begin_pass(&mut encoder, self.sim.physics_module.current);
-            self.sim.follow_module.copy_buffer_to_buffer(&mut encoder);
+            self.sim
+                .follow_module
+                .copy_buffer_to_buffer(&mut encoder, self.frame);
         }
 
         self.gpu.queue.submit(Some(encoder.finish()));
         frame.present();
+        self.sim.follow_module.map_readback();
+        self.sim.group_module.map_readback();
 
         #[cfg(feature = "capture")]
         if let Exists::Some(gfx) = &mut self.gfx {
-            gfx.capture_module.get_frame(&self.gpu.device);
+            gfx.capture_module.map_readback();
+            gfx.capture_module.write_frames(&self.gpu.device);
         }
 
-        if self.sim.follow_module.enabled {
-            if let Some(output) = self.sim.follow_module.get_data(&self.gpu.device) {
-                self.sim.follow_module.info = output;
+        if std::mem::take(&mut self.sim.save_csv) {
+            let result = self
+                .sim
+                .physics_module
+                .read_particles(&self.gpu.device, &self.gpu.queue)
+                .ok_or_else(|| anyhow::anyhow!("Failed to read the particles back from the GPU"))
+                .and_then(|particles| {
+                    csv::write_particles(Path::new(&self.sim.csv_path), &particles)?;
+                    Ok(particles.len())
+                });
+
+            match result {
+                Ok(count) => {
+                    info!("Saved {count} particles to `{}`", self.sim.csv_path);
+                    self.sim.file_error = None;
+                }
+                Err(err) => {
+                    error!("{err:#}");
+                    self.sim.file_error = Some(format!("{err:#}"));
+                }
+            }
+        }
 
-                if self.sim.follow_module.center_of_mass {
-                    self.view_offset = -output.center_of_mass;
-                    self.gfx.render_module.update_offset(
-                        &self.gpu.queue,
-                        self.view_offset.x,
-                        self.view_offset.y,
-                    );
+        let gravity = Gravity {
+            gravitational_constant: self.sim.gravity,
+            delta_time: self.time_scale,
+        };
+        if let Some(catalog) =
+            self.sim
+                .group_module
+                .get_catalog(&self.gpu.device, &self.sim.physics_module, gravity)
+        {
+            let group_module = &mut self.sim.group_module;
+            // Flagged for the follow statistics from the next frame on
+            group_module.cluster = catalog.largest_bound().map(|group| group.label);
+            group_module.update_params(&self.gpu.queue);
+            group_module.catalog = Some(catalog);
+        }
+
+        if let Some(load) = self.sim.load.take() {
+            match self.load(load) {
+                Ok(count) => {
+                    info!("Loaded {count} particles");
+                    self.sim.file_error = None;
+                }
+                Err(err) => {
+                    error!("{err:#}");
+                    self.sim.file_error = Some(format!("{err:#}"));
                 }
+            }
+        }
 
-                if self.sim.follow_module.auto_zoom {
-                    let size = (self.sim.follow_module.info.max_position
-                        - self.sim.follow_module.info.min_position)
-                        .abs();
+        // Arrives a frame or two late
+        if let Some((_, distributions)) = self.sim.follow_module.get_distributions(&self.gpu.device)
+        {
+            self.sim.distributions = Some(distributions);
+        }
+        if let Some((frame, output)) = self.sim.follow_module.get_data(&self.gpu.device) {
+            self.sim.follow_module.info = output;
+            self.plots.push_statistics(frame, &output);
+            self.stats_log.log(&self.tokio_rt, frame, &output);
+
+            if self.sim.follow_module.enabled {
+                if let Some(position) = self
+                    .sim
+                    .follow_module
+                    .target
+                    .and_then(|target| target.position(&output))
+                {
+                    self.camera.follow_offset(-position.as_dvec2());
+                }
 
-                    self.view_zoom = size.length_recip().powf(0.75);
-                    self.gfx
-                        .render_module
-                        .update_zoom(&self.gpu.queue, self.view_zoom);
+                let radius = output.percentile_radius * (1.0 + self.sim.follow_module.zoom_margin);
+                if self.sim.follow_module.auto_zoom && radius > 0.0 {
+                    // Fits the radius into the shorter side of the window
+                    let size = self.gfx.window.inner_size();
+                    let half_size = size.width.min(size.height) as f32 / 2.0;
+                    self.camera
+                        .follow_zoom(half_size / (PIXELS_PER_UNIT * radius));
                 }
             }
         }
 
+        // Fixed framerates are captured frame by frame, so the camera eases in simulated time
+        let delta_time = if self.framerate == 0 {
+            self.framepace.frametime()
+        } else {
+            1.0 / self.framerate as f32
+        };
+        self.camera
+            .update(&self.gpu.queue, &self.gfx.render_module, delta_time);
+
         self.framepace.end_frame(1.0 / self.framerate as f32);
+        self.plots
+            .push_frame_time(self.frame, self.framepace.frametime());
+        self.frame += 1;
     }
 }
@@ -1,40 +1,288 @@
-use glam::Vec2;
-use rand::Rng;
+use anyhow::bail;
+use glam::{DVec2, Vec2};
 
-use crate::physics::PhysicsModule;
+use crate::{physics::PhysicsModule, utils::split_f64};
 
 #[derive(bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+#[allow(dead_code)] // Only read by the shaders
 pub struct Particle {
     pub position: Vec2,
     pub velocity: Vec2,
     pub radius: f32,
     pub mass: f32,
+    /// Low order part of `position`, only used with extended precision
+    pub position_lo: Vec2,
+    /// Timestep level, see [`crate::physics::PhysicsParams::max_timestep_level`]
+    pub level: u32,
+    /// The substep of the current frame this particle has been integrated up to
+    pub time: u32,
+    /// Packed `0xAABBGGRR` sRGB color, `0` colors the particle by its velocity
+    pub color: u32,
+    /// Index of the particle when it was uploaded, spawned particles count up from there and
+    /// a shattered particle keeps it as its first fragment. The top bit marks selected particles
+    /// and the one below the particles of the followed cluster, see [`ID_MASK`].
+    pub id: u32,
 }
 
+/// The bits of [`Particle::id`] that aren't the selection or cluster flag
+pub const ID_MASK: u32 = !(0b11 << 30);
+
 unsafe impl bytemuck::Pod for Particle {}
 
-pub fn generate_particles(queue: &wgpu::Queue, physics_module: &PhysicsModule, num_particles: u64) {
-    let mut rng = rand::thread_rng();
-
-    // Generate Chunks of Random Particles
-    for c in 0..num_particles / 128 {
-        let chunk = Vec2::new(rng.gen_range(-20f32..=20f32), rng.gen_range(-20f32..=20f32));
-        for p in 0..128 {
-            let dir = Vec2::new(rng.gen_range(-1f32..=1f32), rng.gen_range(-1f32..=1f32));
-            let d = rng.gen_range(0.0..=4.0);
-            let particle = Particle {
-                position: chunk + dir * d,
-                velocity: Vec2::ZERO,
-                radius: 0.1, //rng.gen_range(0.01..=0.2f32),
-                mass: 0.1,   //rng.gen_range(0.01..=0.2f32),
-            };
-
-            let i = c + p * (num_particles / 128);
-            queue.write_buffer(
-                physics_module.current_buffer(),
-                i * 24,
-                bytemuck::bytes_of(&particle),
-            );
+impl Particle {
+    /// Creates a particle with `position` split into a double-single `position + position_lo`
+    pub fn new(position: DVec2, velocity: Vec2, radius: f32, mass: f32) -> Self {
+        let (x, x_lo) = split_f64(position.x);
+        let (y, y_lo) = split_f64(position.y);
+
+        Self {
+            position: Vec2::new(x, y),
+            velocity,
+            radius,
+            mass,
+            position_lo: Vec2::new(x_lo, y_lo),
+            level: 0,
+            time: 0,
+            color: 0,
+            id: 0,
+        }
+    }
+
+    /// The full precision position `position + position_lo`
+    pub fn position(&self) -> DVec2 {
+        self.position.as_dvec2() + self.position_lo.as_dvec2()
+    }
+
+    pub fn set_position(&mut self, position: DVec2) {
+        let (x, x_lo) = split_f64(position.x);
+        let (y, y_lo) = split_f64(position.y);
+
+        self.position = Vec2::new(x, y);
+        self.position_lo = Vec2::new(x_lo, y_lo);
+    }
+}
+
+/// Particles built on the CPU by the generators and importers before they are uploaded
+#[derive(Clone, Default)]
+pub struct ParticleSet {
+    particles: Vec<Particle>,
+}
+
+impl ParticleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            particles: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, particle: Particle) {
+        self.particles.push(particle);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.particles.truncate(len);
+    }
+
+    /// Appends all particles of `other`
+    pub fn merge(&mut self, other: ParticleSet) {
+        self.particles.extend(other.particles);
+    }
+
+    pub fn translate(&mut self, offset: DVec2) {
+        for particle in &mut self.particles {
+            particle.set_position(particle.position() + offset);
+        }
+    }
+
+    /// Rotates the positions around the origin and the velocities by `angle` in radians
+    pub fn rotate(&mut self, angle: f32) {
+        let rotation = Vec2::from_angle(angle);
+        for particle in &mut self.particles {
+            particle.set_position(rotation.as_dvec2().rotate(particle.position()));
+            particle.velocity = rotation.rotate(particle.velocity);
+        }
+    }
+
+    /// Scales the positions away from the origin
+    pub fn scale(&mut self, factor: f64) {
+        for particle in &mut self.particles {
+            particle.set_position(particle.position() * factor);
+        }
+    }
+
+    /// Adds `velocity` to every particle
+    pub fn boost(&mut self, velocity: Vec2) {
+        for particle in &mut self.particles {
+            particle.velocity += velocity;
+        }
+    }
+
+    /// Checks that there are particles and that they can be simulated,
+    /// a mass of `0` would mark them as dead
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.particles.is_empty() {
+            bail!("no particles");
+        }
+
+        for (i, particle) in self.particles.iter().enumerate() {
+            let number = i + 1;
+            if !particle.position().is_finite() || !particle.velocity.is_finite() {
+                bail!("particle {number}: the position and velocity must be finite");
+            }
+            if !(particle.mass > 0.0 && particle.mass.is_finite()) {
+                bail!(
+                    "particle {number}: the mass must be positive but is {}",
+                    particle.mass
+                );
+            }
+            if !(particle.radius >= 0.0 && particle.radius.is_finite()) {
+                bail!(
+                    "particle {number}: the radius must not be negative but is {}",
+                    particle.radius
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Numbers the particles and replaces the particles on the GPU in a single write,
+    /// particles beyond the capacity are dropped. Returns the number uploaded.
+    pub fn upload(&mut self, queue: &wgpu::Queue, physics_module: &mut PhysicsModule) -> u32 {
+        for (i, particle) in self.particles.iter_mut().enumerate() {
+            particle.id = i as u32 & ID_MASK;
         }
+
+        let particles = &self.particles[..self.particles.len().min(physics_module.capacity)];
+        queue.write_buffer(
+            physics_module.current_buffer(),
+            0,
+            bytemuck::cast_slice(particles),
+        );
+
+        let count = particles.len() as u32;
+        physics_module.set_particle_count(queue, count);
+        count
+    }
+}
+
+impl std::ops::Deref for ParticleSet {
+    type Target = [Particle];
+
+    fn deref(&self) -> &Self::Target {
+        &self.particles
+    }
+}
+
+impl std::ops::DerefMut for ParticleSet {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.particles
+    }
+}
+
+impl From<Vec<Particle>> for ParticleSet {
+    fn from(particles: Vec<Particle>) -> Self {
+        Self { particles }
+    }
+}
+
+impl FromIterator<Particle> for ParticleSet {
+    fn from_iter<T: IntoIterator<Item = Particle>>(iter: T) -> Self {
+        Self {
+            particles: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<Particle> for ParticleSet {
+    fn extend<T: IntoIterator<Item = Particle>>(&mut self, iter: T) {
+        self.particles.extend(iter);
+    }
+}
+
+/// Two particles flying head-on into each other at `speed`,
+/// they have to collide no matter how far they move in a single step
+pub fn head_on(speed: f32) -> ParticleSet {
+    ParticleSet::from(vec![
+        Particle::new(DVec2::new(-5.0, 0.0), Vec2::new(speed, 0.0), 0.1, 0.1),
+        Particle::new(DVec2::new(5.0, 0.0), Vec2::new(-speed, 0.0), 0.1, 0.1),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(x: f64, y: f64) -> Particle {
+        Particle::new(DVec2::new(x, y), Vec2::new(1.0, 0.0), 0.5, 2.0)
+    }
+
+    #[test]
+    fn positions_keep_double_precision() {
+        let position = DVec2::new(1e8 + 0.125, -3e7 - 0.0625);
+        let particle = Particle::new(position, Vec2::ZERO, 1.0, 1.0);
+
+        assert_eq!(particle.position(), position);
+        // The high part alone can't represent it
+        assert_ne!(particle.position.as_dvec2(), position);
+    }
+
+    #[test]
+    fn transforms() {
+        let mut particles = ParticleSet::from(vec![particle(1.0, 0.0), particle(0.0, 2.0)]);
+
+        particles.scale(2.0);
+        assert_eq!(particles[0].position(), DVec2::new(2.0, 0.0));
+        assert_eq!(particles[1].position(), DVec2::new(0.0, 4.0));
+
+        particles.rotate(std::f32::consts::FRAC_PI_2);
+        assert!((particles[0].position() - DVec2::new(0.0, 2.0)).length() < 1e-6);
+        assert!((particles[1].position() - DVec2::new(-4.0, 0.0)).length() < 1e-6);
+        assert!((particles[0].velocity - Vec2::new(0.0, 1.0)).length() < 1e-6);
+
+        particles.translate(DVec2::new(1e9, 0.5));
+        assert!((particles[1].position() - DVec2::new(1e9 - 4.0, 0.5)).length() < 1e-6);
+
+        particles.boost(Vec2::new(0.0, -1.0));
+        for particle in particles.iter() {
+            assert!(particle.velocity.length() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn merge_appends_in_order() {
+        let mut particles = ParticleSet::from(vec![particle(1.0, 0.0)]);
+        particles.merge(ParticleSet::from(vec![
+            particle(2.0, 0.0),
+            particle(3.0, 0.0),
+        ]));
+
+        let xs: Vec<f64> = particles.iter().map(|p| p.position().x).collect();
+        assert_eq!(xs, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn validate() {
+        let valid = ParticleSet::from(vec![particle(0.0, 0.0), particle(1.0, 1.0)]);
+        valid.validate().unwrap();
+
+        let err = ParticleSet::new().validate().unwrap_err();
+        assert_eq!(err.to_string(), "no particles");
+
+        let invalid = |change: fn(&mut Particle)| {
+            let mut particles = valid.clone();
+            change(&mut particles[1]);
+            particles.validate().unwrap_err().to_string()
+        };
+        assert!(invalid(|p| p.mass = 0.0).starts_with("particle 2: the mass must be positive"));
+        assert!(invalid(|p| p.mass = f32::INFINITY).contains("mass"));
+        assert!(invalid(|p| p.radius = -1.0).contains("radius"));
+        assert!(invalid(|p| p.velocity.x = f32::NAN).contains("finite"));
+        assert!(invalid(|p| p.position_lo.y = f32::NAN).contains("finite"));
     }
 }
@@ -1,32 +1,196 @@
 use std::borrow::Cow;
 
+use glam::Vec2;
 use wgpu::util::DeviceExt;
 
-use crate::particle::Particle;
+use crate::particle::{Particle, ParticleSet, ID_MASK};
+
+/// Byte offset of the `dispatch_workgroups_indirect` arguments in [`PhysicsModule::indirect_buffer`]
+pub const DISPATCH_INDIRECT_OFFSET: u64 = 0;
+/// Byte offset of the `draw_indirect` arguments in [`PhysicsModule::indirect_buffer`]
+pub const DRAW_INDIRECT_OFFSET: u64 = 3 * 4;
+/// Byte offset of the dispatch arguments over the active particles of a substep
+pub const ACTIVE_DISPATCH_INDIRECT_OFFSET: u64 = 7 * 4;
+
+/// The largest `max_timestep_level`, a frame is split into at most `2^MAX_TIMESTEP_LEVEL` substeps
+pub const MAX_TIMESTEP_LEVEL: u32 = 8;
+
+/// The most fragments a particle can shatter into, see `physics.wgsl`
+pub const MAX_FRAGMENTS: u32 = 8;
+
+/// Workgroups of the `spawn` pass, has to match `alloc.wgsl`
+const SPAWN_WORKGROUPS: u32 = 64;
+
+/// Distances below this are clamped in the gravity of `physics.wgsl`
+pub const MIN_GRAVITY_DISTANCE: f32 = 0.01;
+
+/// The force law of `physics.wgsl`, every frame a particle's velocity changes by
+/// `gravitational_constant * mass * other_mass / distance` towards every other particle
+#[derive(Clone, Copy)]
+pub struct Gravity {
+    pub gravitational_constant: f32,
+    /// The simulated time of one frame
+    pub delta_time: f32,
+}
+
+impl Gravity {
+    /// Acceleration of a particle of `mass` towards `other_mass` at `distance`
+    pub fn acceleration(&self, mass: f32, other_mass: f32, distance: f32) -> f32 {
+        self.gravitational_constant * mass * other_mass
+            / (distance.max(MIN_GRAVITY_DISTANCE) * self.delta_time)
+    }
+
+    /// Speed of a circular orbit at `radius` around `enclosed_mass`,
+    /// with a `1 / r` force the mass of a rotationally symmetric distribution
+    /// acts as if it was all at the center
+    pub fn circular_speed(&self, mass: f32, enclosed_mass: f32, radius: f32) -> f32 {
+        (self.acceleration(mass, enclosed_mass, radius) * radius).sqrt()
+    }
+
+    /// Mean squared speed that keeps particles of `mass` in a cluster of `total_mass`
+    /// in virial equilibrium `2K + W = 0`, with a `1 / r` force this doesn't depend on its size
+    pub fn virial_speed_squared(&self, mass: f32, total_mass: f32) -> f32 {
+        self.gravitational_constant * mass * (total_mass - mass).max(0.0) / (2.0 * self.delta_time)
+    }
+
+    /// Magnitude of the virial `W` of a cluster from the sums of its masses, squared masses and
+    /// cubed masses, the `Σ m v²` it has in virial equilibrium with [`Self::virial_speed_squared`]
+    pub fn virial(&self, mass: f64, mass_squared: f64, mass_cubed: f64) -> f64 {
+        self.gravitational_constant as f64 * (mass * mass_squared - mass_cubed)
+            / (2.0 * self.delta_time as f64)
+    }
+
+    /// Relative velocity of two bodies of `mass` and `other_mass` at `radius` on an orbit
+    /// between `periapsis` and `apoapsis` as `(radial, tangential)`, the `1 / r` force has
+    /// a logarithmic potential so the orbit keeps both distances but precesses into a rosette
+    /// instead of closing like an ellipse
+    pub fn orbital_velocity(
+        &self,
+        mass: f32,
+        other_mass: f32,
+        periapsis: f32,
+        apoapsis: f32,
+        radius: f32,
+    ) -> Vec2 {
+        // Both bodies are pulled towards each other by the same amount, `acceleration * distance`
+        // is constant outside of the softening
+        let k = 2.0 * self.acceleration(mass, other_mass, 1.0);
+
+        // Energy `v^2 / 2 + k ln(r)` and angular momentum `L = v_t r` are the same at both apsides
+        let angular_momentum_squared = if apoapsis - periapsis <= apoapsis * 1e-5 {
+            k * periapsis * periapsis
+        } else {
+            2.0 * k * (apoapsis / periapsis).ln() / (periapsis.powi(-2) - apoapsis.powi(-2))
+        };
+
+        let speed_squared = angular_momentum_squared / (periapsis * periapsis)
+            + 2.0 * k * (periapsis / radius).ln();
+        let tangential = angular_momentum_squared.sqrt() / radius;
+        let radial = (speed_squared - tangential * tangential).max(0.0).sqrt();
+
+        Vec2::new(radial, tangential)
+    }
+}
+
+/// The uniform parameters of `physics.wgsl`
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+pub struct PhysicsParams {
+    pub delta_time: f32,
+    pub gravitational_constant: f32,
+    /// Integrate positions as double-single floats (`position + position_lo`)
+    pub extended_precision: u32,
+    /// Particles are integrated with individual timesteps of `delta_time / 2^level`
+    /// where `level <= max_timestep_level`, `0` uses one global timestep
+    pub max_timestep_level: u32,
+    /// Scales the timestep a particle picks from its acceleration, smaller is more accurate
+    pub timestep_accuracy: f32,
+    /// Sweep particles along their path to catch collisions between steps
+    pub continuous_collisions: u32,
+    /// Relative impact speed above which colliding particles shatter, `0` disables fragmentation
+    pub fragmentation_speed: f32,
+    /// Fragments a shattered particle splits into, at most `MAX_FRAGMENTS`
+    pub fragment_count: u32,
+    /// Particles whose fragments would be lighter than this don't shatter
+    pub min_fragment_mass: f32,
+    /// Speed of the ejecta relative to the impact speed
+    pub ejecta_speed: f32,
+}
+
+/// GPU side particle allocation state, see `alloc.wgsl`
+#[derive(Default, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+pub struct Allocator {
+    pub capacity: u32,
+    /// Slots in use, dead particles below `count` are kept on the free list
+    pub count: u32,
+    pub alive: u32,
+    pub free_count: u32,
+    pub compacted: u32,
+    /// Particles waiting to be spawned after the current substep
+    pub spawn_count: u32,
+    /// The id given to the next spawned particle, emitted ones and fragments alike
+    pub next_id: u32,
+}
 
 pub struct PhysicsModule {
     pub particle_buffers: [wgpu::Buffer; 2],
     pub param_buffer: wgpu::Buffer,
+    pub allocator_buffer: wgpu::Buffer,
+    pub free_list_buffer: wgpu::Buffer,
+    spawn_queue_buffer: wgpu::Buffer,
+    pub indirect_buffer: wgpu::Buffer,
+    scheduler_buffer: wgpu::Buffer,
+    active_list_buffer: wgpu::Buffer,
+    substep_buffer: wgpu::Buffer,
+    substep_stride: u32,
 
     pub current: usize,
+    pub capacity: usize,
+    pub max_timestep_level: u32,
+    fragmentation: bool,
+    /// Particles queued with `emit` for the next step
+    emitted: u32,
+
+    /// Compact the particle buffers every `compaction_interval` steps, `0` disables compaction
+    pub compaction_interval: u32,
+    steps_since_compaction: u32,
 
     bind_group_layout: wgpu::BindGroupLayout,
+    allocator_bind_group_layout: wgpu::BindGroupLayout,
+    schedule_bind_group_layout: wgpu::BindGroupLayout,
     pub bind_groups: [wgpu::BindGroup; 2],
+    allocator_bind_group: wgpu::BindGroup,
+    schedule_bind_group: wgpu::BindGroup,
+    indirect_bind_group: wgpu::BindGroup,
     pub pipeline: wgpu::ComputePipeline,
+    schedule_pipeline: wgpu::ComputePipeline,
+    finish_schedule_pipeline: wgpu::ComputePipeline,
+
+    prepare_pipeline: wgpu::ComputePipeline,
+    collect_free_pipeline: wgpu::ComputePipeline,
+    compact_pipeline: wgpu::ComputePipeline,
+    finish_compaction_pipeline: wgpu::ComputePipeline,
+    spawn_pipeline: wgpu::ComputePipeline,
+    finish_spawn_pipeline: wgpu::ComputePipeline,
 }
 
 impl PhysicsModule {
-    pub fn new(device: &wgpu::Device, max_particles: usize, gravitational_constant: f32) -> Self {
+    pub fn new(device: &wgpu::Device, max_particles: usize, params: PhysicsParams) -> Self {
         let physics_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
             source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("physics.wgsl"))),
         });
+        let alloc_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("alloc.wgsl"))),
+        });
 
         // https://github.com/gfx-rs/wgpu/blob/trunk/examples/src/hello_compute/mod.rs
         // https://github.com/gfx-rs/wgpu/blob/trunk/examples/src/boids/mod.rs
         let param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Physics Parameter Buffer"),
-            contents: bytemuck::cast_slice(&[1.0f32, gravitational_constant]),
+            contents: bytemuck::bytes_of(&params),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
@@ -66,30 +230,302 @@ impl PhysicsModule {
             ],
         });
 
+        let allocator_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let schedule_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(4),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let indirect_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
         let (particle_buffers, bind_groups) =
             create_buffer_group(device, &bind_group_layout, &param_buffer, max_particles);
 
+        let allocator_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Allocator Buffer"),
+            contents: bytemuck::bytes_of(&Allocator {
+                capacity: max_particles as u32,
+                ..Default::default()
+            }),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let free_list_buffer =
+            create_index_buffer(device, "Particle Free List Buffer", max_particles);
+        let spawn_queue_buffer = create_spawn_queue_buffer(device, max_particles);
+        let allocator_bind_group = create_allocator_bind_group(
+            device,
+            &allocator_bind_group_layout,
+            &allocator_buffer,
+            &free_list_buffer,
+            &spawn_queue_buffer,
+        );
+
+        // [dispatch_x, dispatch_y, dispatch_z, vertex_count, instance_count, first_vertex, first_instance,
+        //  active_dispatch_x, active_dispatch_y, active_dispatch_z]
+        let indirect_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Indirect Buffer"),
+            contents: bytemuck::cast_slice(&[0u32, 1, 1, 3, 0, 0, 0, 0, 1, 1]),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+        let indirect_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &indirect_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: indirect_buffer.as_entire_binding(),
+            }],
+        });
+
+        // [counter, active_count]
+        let scheduler_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Physics Scheduler Buffer"),
+            size: 2 * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let active_list_buffer =
+            create_index_buffer(device, "Active Particle Buffer", max_particles);
+
+        // The index of every substep at a dynamic offset
+        let substep_stride = device.limits().min_uniform_buffer_offset_alignment;
+        let substeps: Vec<u32> = (0..1 << MAX_TIMESTEP_LEVEL)
+            .flat_map(|substep| {
+                let mut aligned = vec![0u32; substep_stride as usize / 4];
+                aligned[0] = substep;
+                aligned
+            })
+            .collect();
+        let substep_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Physics Substep Buffer"),
+            contents: bytemuck::cast_slice(&substeps),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let schedule_bind_group = create_schedule_bind_group(
+            device,
+            &schedule_bind_group_layout,
+            &scheduler_buffer,
+            &active_list_buffer,
+            &substep_buffer,
+        );
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &allocator_bind_group_layout],
             push_constant_ranges: &[],
         });
+        let schedule_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    &allocator_bind_group_layout,
+                    &schedule_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
         let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: None,
-            layout: Some(&pipeline_layout),
+            layout: Some(&schedule_pipeline_layout),
             module: &physics_shader,
             entry_point: "main",
         });
+        let schedule_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&schedule_pipeline_layout),
+            module: &physics_shader,
+            entry_point: "schedule",
+        });
+        let finish_schedule_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    &allocator_bind_group_layout,
+                    &schedule_bind_group_layout,
+                    &indirect_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let finish_schedule_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&finish_schedule_pipeline_layout),
+                module: &physics_shader,
+                entry_point: "finish_schedule",
+            });
+
+        // The indirect buffer can't be bound while it's used for `dispatch_workgroups_indirect`
+        let indirect_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    &allocator_bind_group_layout,
+                    &indirect_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let prepare_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&indirect_pipeline_layout),
+            module: &alloc_shader,
+            entry_point: "prepare",
+        });
+        let collect_free_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &alloc_shader,
+                entry_point: "collect_free",
+            });
+        let compact_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &alloc_shader,
+            entry_point: "compact",
+        });
+        let finish_compaction_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&indirect_pipeline_layout),
+                module: &alloc_shader,
+                entry_point: "finish_compaction",
+            });
+        let spawn_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &alloc_shader,
+            entry_point: "spawn",
+        });
+        let finish_spawn_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&indirect_pipeline_layout),
+                module: &alloc_shader,
+                entry_point: "finish_spawn",
+            });
 
         Self {
             particle_buffers,
             param_buffer,
+            allocator_buffer,
+            free_list_buffer,
+            spawn_queue_buffer,
+            indirect_buffer,
+            scheduler_buffer,
+            active_list_buffer,
+            substep_buffer,
+            substep_stride,
 
             current: 0,
+            capacity: max_particles,
+            max_timestep_level: params.max_timestep_level,
+            fragmentation: params.fragmentation_speed > 0.0,
+            emitted: 0,
+
+            compaction_interval: 64,
+            steps_since_compaction: 0,
 
             bind_group_layout,
+            allocator_bind_group_layout,
+            schedule_bind_group_layout,
             bind_groups,
+            allocator_bind_group,
+            schedule_bind_group,
+            indirect_bind_group,
             pipeline,
+            schedule_pipeline,
+            finish_schedule_pipeline,
+
+            prepare_pipeline,
+            collect_free_pipeline,
+            compact_pipeline,
+            finish_compaction_pipeline,
+            spawn_pipeline,
+            finish_spawn_pipeline,
         }
     }
 
@@ -100,20 +536,265 @@ impl PhysicsModule {
             &self.param_buffer,
             num_particles,
         );
+        let free_list_buffer =
+            create_index_buffer(device, "Particle Free List Buffer", num_particles);
+        let active_list_buffer =
+            create_index_buffer(device, "Active Particle Buffer", num_particles);
+        let spawn_queue_buffer = create_spawn_queue_buffer(device, num_particles);
 
+        self.schedule_bind_group = create_schedule_bind_group(
+            device,
+            &self.schedule_bind_group_layout,
+            &self.scheduler_buffer,
+            &active_list_buffer,
+            &self.substep_buffer,
+        );
+        self.allocator_bind_group = create_allocator_bind_group(
+            device,
+            &self.allocator_bind_group_layout,
+            &self.allocator_buffer,
+            &free_list_buffer,
+            &spawn_queue_buffer,
+        );
         self.particle_buffers = particle_buffers;
+        self.free_list_buffer = free_list_buffer;
+        self.spawn_queue_buffer = spawn_queue_buffer;
+        self.active_list_buffer = active_list_buffer;
         self.bind_groups = bind_groups;
+        self.capacity = num_particles;
+        self.current = 0;
+        self.emitted = 0;
     }
 
     pub fn current_buffer(&self) -> &wgpu::Buffer {
         &self.particle_buffers[self.current]
     }
 
-    pub fn begin_pass<'a>(
-        &'a mut self,
-        encoder: &'a mut wgpu::CommandEncoder,
-        work_group_count: u32,
-    ) -> wgpu::ComputePass<'a> {
+    /// Resets the allocator so the first `count` particles of the current buffer are in use
+    pub fn set_particle_count(&mut self, queue: &wgpu::Queue, count: u32) {
+        let allocator = Allocator {
+            capacity: self.capacity as u32,
+            count: count.min(self.capacity as u32),
+            next_id: count & ID_MASK,
+            ..Default::default()
+        };
+
+        queue.write_buffer(&self.allocator_buffer, 0, bytemuck::bytes_of(&allocator));
+        self.steps_since_compaction = 0;
+        self.emitted = 0;
+    }
+
+    /// Queues `particles` after the ones already queued to be added at the start of the next step,
+    /// the ones that don't fit into the buffers are dropped. They get their ids when spawned.
+    pub fn emit(&mut self, queue: &wgpu::Queue, particles: &[Particle]) {
+        let count = particles
+            .len()
+            .min(self.capacity.saturating_sub(self.emitted as usize));
+        let particles = &particles[..count];
+        if particles.is_empty() {
+            return;
+        }
+
+        let offset = self.emitted as u64 * std::mem::size_of::<Particle>() as u64;
+        self.emitted += particles.len() as u32;
+        queue.write_buffer(
+            &self.spawn_queue_buffer,
+            offset,
+            bytemuck::cast_slice(particles),
+        );
+        queue.write_buffer(
+            &self.allocator_buffer,
+            std::mem::offset_of!(Allocator, spawn_count) as u64,
+            bytemuck::bytes_of(&self.emitted),
+        );
+    }
+
+    /// Reads the living particles of the current buffer back to the CPU, blocks until the GPU is done
+    pub fn read_particles(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Option<ParticleSet> {
+        let allocator_size = std::mem::size_of::<Allocator>() as u64;
+        let particles_size = self.current_buffer().size();
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Readback Buffer"),
+            size: allocator_size + particles_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(
+            &self.allocator_buffer,
+            0,
+            &staging_buffer,
+            0,
+            allocator_size,
+        );
+        encoder.copy_buffer_to_buffer(
+            self.current_buffer(),
+            0,
+            &staging_buffer,
+            allocator_size,
+            particles_size,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = staging_buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        slice.map_async(wgpu::MapMode::Read, move |v| tx.send(v).unwrap());
+
+        device.poll(wgpu::Maintain::wait()).panic_on_timeout();
+        if let Ok(Ok(())) = rx.recv() {
+            let data = slice.get_mapped_range();
+            let (allocator, particles) = data.split_at(allocator_size as usize);
+            let allocator: Allocator = bytemuck::pod_read_unaligned(allocator);
+            let particles: ParticleSet = bytemuck::cast_slice::<_, Particle>(particles)
+                [..allocator.count.min(self.capacity as u32) as usize]
+                .iter()
+                .filter(|particle| particle.mass != 0.0)
+                .copied()
+                .collect();
+
+            drop(data);
+            staging_buffer.unmap();
+            Some(particles)
+        } else {
+            None
+        }
+    }
+
+    /// Updates the indirect arguments from the allocator, this has to run every frame
+    pub fn prepare(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+
+        cpass.set_pipeline(&self.prepare_pipeline);
+        cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+        cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+        cpass.set_bind_group(2, &self.indirect_bind_group, &[]);
+        cpass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Advances the simulation by one frame in `2^max_timestep_level` substeps,
+    /// only the particles whose timestep ends on a substep are integrated in it
+    pub fn step(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+
+            cpass.set_pipeline(&self.collect_free_pipeline);
+            cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+            cpass.dispatch_workgroups_indirect(&self.indirect_buffer, DISPATCH_INDIRECT_OFFSET);
+        }
+        if std::mem::take(&mut self.emitted) > 0 {
+            // The other bind group writes to the current buffer
+            self.spawn(encoder, (self.current + 1) % 2);
+        }
+
+        for substep in 0..1u32 << self.max_timestep_level {
+            let substep_offset = substep * self.substep_stride;
+
+            {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: None,
+                    timestamp_writes: None,
+                });
+
+                cpass.set_pipeline(&self.schedule_pipeline);
+                cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+                cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+                cpass.set_bind_group(2, &self.schedule_bind_group, &[substep_offset]);
+                cpass.dispatch_workgroups_indirect(&self.indirect_buffer, DISPATCH_INDIRECT_OFFSET);
+            }
+            {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: None,
+                    timestamp_writes: None,
+                });
+
+                cpass.set_pipeline(&self.finish_schedule_pipeline);
+                cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+                cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+                cpass.set_bind_group(2, &self.schedule_bind_group, &[substep_offset]);
+                cpass.set_bind_group(3, &self.indirect_bind_group, &[]);
+                cpass.dispatch_workgroups(1, 1, 1);
+            }
+            {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: None,
+                    timestamp_writes: None,
+                });
+
+                cpass.set_pipeline(&self.pipeline);
+                cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+                cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+                cpass.set_bind_group(2, &self.schedule_bind_group, &[substep_offset]);
+                cpass.dispatch_workgroups_indirect(
+                    &self.indirect_buffer,
+                    ACTIVE_DISPATCH_INDIRECT_OFFSET,
+                );
+            }
+            if self.fragmentation {
+                self.spawn(encoder, self.current);
+            }
+
+            self.current = (self.current + 1) % 2;
+        }
+
+        self.steps_since_compaction += 1;
+        if self.compaction_interval != 0 && self.steps_since_compaction >= self.compaction_interval
+        {
+            self.compact(encoder);
+        }
+    }
+
+    /// Moves the queued particles into the output buffer of `bind_groups[bind_group]`
+    fn spawn(&self, encoder: &mut wgpu::CommandEncoder, bind_group: usize) {
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+
+            cpass.set_pipeline(&self.spawn_pipeline);
+            cpass.set_bind_group(0, &self.bind_groups[bind_group], &[]);
+            cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+            cpass.dispatch_workgroups(SPAWN_WORKGROUPS, 1, 1);
+        }
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+
+        cpass.set_pipeline(&self.finish_spawn_pipeline);
+        cpass.set_bind_group(0, &self.bind_groups[bind_group], &[]);
+        cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+        cpass.set_bind_group(2, &self.indirect_bind_group, &[]);
+        cpass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Moves all living particles to the front of the other buffer and shrinks the slots in use
+    pub fn compact(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+
+            cpass.set_pipeline(&self.compact_pipeline);
+            cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+            cpass.dispatch_workgroups_indirect(&self.indirect_buffer, DISPATCH_INDIRECT_OFFSET);
+        }
         self.current = (self.current + 1) % 2;
 
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
@@ -121,22 +802,174 @@ impl PhysicsModule {
             timestamp_writes: None,
         });
 
-        cpass.set_pipeline(&self.pipeline);
-        cpass.set_bind_group(0, &self.bind_groups[1 - self.current], &[]);
-        cpass.dispatch_workgroups(work_group_count, 1, 1);
+        cpass.set_pipeline(&self.finish_compaction_pipeline);
+        cpass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+        cpass.set_bind_group(1, &self.allocator_bind_group, &[]);
+        cpass.set_bind_group(2, &self.indirect_bind_group, &[]);
+        cpass.dispatch_workgroups(1, 1, 1);
 
-        cpass
+        self.steps_since_compaction = 0;
     }
 
     pub fn update_delta_time(&self, queue: &wgpu::Queue, dt: f32) {
-        queue.write_buffer(&self.param_buffer, 0, bytemuck::bytes_of(&dt));
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, delta_time) as u64,
+            bytemuck::bytes_of(&dt),
+        );
     }
 
     pub fn update_gravitational_constant(&self, queue: &wgpu::Queue, g: f32) {
-        queue.write_buffer(&self.param_buffer, 4, bytemuck::bytes_of(&g));
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, gravitational_constant) as u64,
+            bytemuck::bytes_of(&g),
+        );
+    }
+
+    pub fn update_timestep_levels(&mut self, queue: &wgpu::Queue, max_level: u32, accuracy: f32) {
+        self.max_timestep_level = max_level.min(MAX_TIMESTEP_LEVEL);
+
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, max_timestep_level) as u64,
+            bytemuck::bytes_of(&self.max_timestep_level),
+        );
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, timestep_accuracy) as u64,
+            bytemuck::bytes_of(&accuracy),
+        );
+    }
+
+    pub fn update_continuous_collisions(&self, queue: &wgpu::Queue, enabled: bool) {
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, continuous_collisions) as u64,
+            bytemuck::bytes_of(&(enabled as u32)),
+        );
+    }
+
+    pub fn update_fragmentation(
+        &mut self,
+        queue: &wgpu::Queue,
+        speed: f32,
+        fragment_count: u32,
+        min_fragment_mass: f32,
+        ejecta_speed: f32,
+    ) {
+        self.fragmentation = speed > 0.0;
+
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, fragmentation_speed) as u64,
+            bytemuck::bytes_of(&speed.max(0.0)),
+        );
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, fragment_count) as u64,
+            bytemuck::bytes_of(&fragment_count.clamp(2, MAX_FRAGMENTS)),
+        );
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, min_fragment_mass) as u64,
+            bytemuck::bytes_of(&min_fragment_mass),
+        );
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, ejecta_speed) as u64,
+            bytemuck::bytes_of(&ejecta_speed),
+        );
+    }
+
+    pub fn update_extended_precision(&self, queue: &wgpu::Queue, enabled: bool) {
+        queue.write_buffer(
+            &self.param_buffer,
+            std::mem::offset_of!(PhysicsParams, extended_precision) as u64,
+            bytemuck::bytes_of(&(enabled as u32)),
+        );
     }
 }
 
+/// A buffer holding one `u32` particle index per particle
+fn create_index_buffer(device: &wgpu::Device, label: &str, num_particles: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (std::mem::size_of::<u32>() * num_particles) as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+/// Holds the particles queued by `physics.wgsl` or `PhysicsModule::emit`
+/// until the `spawn` pass places them
+fn create_spawn_queue_buffer(device: &wgpu::Device, num_particles: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Particle Spawn Queue Buffer"),
+        size: (std::mem::size_of::<Particle>() * num_particles) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_allocator_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    allocator_buffer: &wgpu::Buffer,
+    free_list_buffer: &wgpu::Buffer,
+    spawn_queue_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: allocator_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: free_list_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: spawn_queue_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_schedule_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scheduler_buffer: &wgpu::Buffer,
+    active_list_buffer: &wgpu::Buffer,
+    substep_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: scheduler_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: active_list_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: substep_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(4),
+                }),
+            },
+        ],
+    })
+}
+
 fn create_buffer_group(
     device: &wgpu::Device,
     bind_group_layout: &wgpu::BindGroupLayout,
@@ -148,7 +981,8 @@ fn create_buffer_group(
         size: (std::mem::size_of::<Particle>() * num_particles) as u64,
         usage: wgpu::BufferUsages::VERTEX
             | wgpu::BufferUsages::STORAGE
-            | wgpu::BufferUsages::COPY_DST,
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
         mapped_at_creation: false,
     });
     let pbb = device.create_buffer(&wgpu::BufferDescriptor {
@@ -156,7 +990,8 @@ fn create_buffer_group(
         size: (std::mem::size_of::<Particle>() * num_particles) as u64,
         usage: wgpu::BufferUsages::VERTEX
             | wgpu::BufferUsages::STORAGE
-            | wgpu::BufferUsages::COPY_DST,
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
         mapped_at_creation: false,
     });
 
@@ -199,3 +1034,103 @@ fn create_buffer_group(
 
     ([pba, pbb], [bga, bgb])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{benchmark::headless_device, particle};
+
+    fn params(time_scale: f32, fragmentation_speed: f32) -> PhysicsParams {
+        PhysicsParams {
+            delta_time: time_scale,
+            gravitational_constant: 0.1,
+            extended_precision: 0,
+            max_timestep_level: 0,
+            timestep_accuracy: 1.0,
+            continuous_collisions: 1,
+            fragmentation_speed,
+            fragment_count: 4,
+            min_fragment_mass: 0.0,
+            ejecta_speed: 0.25,
+        }
+    }
+
+    fn step(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        physics_module: &mut PhysicsModule,
+    ) -> ParticleSet {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        physics_module.prepare(&mut encoder);
+        physics_module.step(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+
+        physics_module.read_particles(device, queue).unwrap()
+    }
+
+    /// Steps two particles flying head-on into each other and checks that they never swap sides,
+    /// no matter how far they move in a single frame
+    #[test]
+    fn head_on_particles_collide() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let Ok((device, queue)) = runtime.block_on(headless_device()) else {
+            eprintln!("Skipped, no GPU adapter");
+            return;
+        };
+
+        for time_scale in [1.0 / 60.0, 0.1, 1.0, 10.0] {
+            for speed in [10.0, 1000.0] {
+                let mut physics_module = PhysicsModule::new(&device, 2, params(time_scale, 0.0));
+                particle::head_on(speed).upload(&queue, &mut physics_module);
+
+                for frame in 0..20 {
+                    let particles = step(&device, &queue, &mut physics_module);
+                    let [left, right] = [0, 1].map(|id| {
+                        particles
+                            .iter()
+                            .find(|particle| particle.id & ID_MASK == id)
+                            .unwrap()
+                            .position()
+                            .x
+                    });
+                    assert!(
+                        left < right,
+                        "passed each other in frame {frame} at a time scale of {time_scale} \
+                         and a speed of {speed}: {left} >= {right}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Shatters two particles with room for none, some or all of their fragments
+    /// and checks that no mass is lost or made up and every fragment gets its own id
+    #[test]
+    fn fragments_keep_the_mass() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let Ok((device, queue)) = runtime.block_on(headless_device()) else {
+            eprintln!("Skipped, no GPU adapter");
+            return;
+        };
+
+        for capacity in [2, 5, 6, 8, 16] {
+            let mut physics_module = PhysicsModule::new(&device, capacity, params(0.1, 1.0));
+            particle::head_on(100.0).upload(&queue, &mut physics_module);
+
+            for frame in 0..5 {
+                let particles = step(&device, &queue, &mut physics_module);
+                let mass: f32 = particles.iter().map(|particle| particle.mass).sum();
+                assert!(particles.len() <= capacity);
+                let mut ids: Vec<_> = particles.iter().map(|p| p.id & ID_MASK).collect();
+                ids.sort_unstable();
+                ids.dedup();
+                assert_eq!(ids.len(), particles.len(), "ids aren't unique: {ids:?}");
+                assert!(
+                    (mass - 0.2).abs() < 1e-6,
+                    "frame {frame} with a capacity of {capacity} has a mass of {mass}"
+                );
+            }
+        }
+    }
+}
@@ -10,9 +10,78 @@ var<storage, read_write> output: array<Particle>;
 @binding(2)
 var<uniform> params: PhysicsParams;
 
+@group(1)
+@binding(0)
+var<storage, read_write> allocator: Allocator;
+
+@group(1)
+@binding(1)
+var<storage, read_write> free_list: array<u32>;
+
+@group(1)
+@binding(2)
+var<storage, read_write> spawn_queue: array<Particle>;
+
+@group(2)
+@binding(0)
+var<storage, read_write> scheduler: Scheduler;
+
+@group(2)
+@binding(1)
+var<storage, read_write> active_list: array<u32>;
+
+@group(2)
+@binding(2)
+var<uniform> substep: u32;
+
+@group(3)
+@binding(0)
+var<storage, read_write> indirect: IndirectArgs;
+
 struct PhysicsParams {
     delta_time: f32,
     gravitational_constant: f32,
+    extended_precision: u32,
+    max_timestep_level: u32,
+    timestep_accuracy: f32,
+    continuous_collisions: u32,
+    // Relative impact speed above which particles shatter, `0` disables fragmentation
+    fragmentation_speed: f32,
+    fragment_count: u32,
+    min_fragment_mass: f32,
+    // Speed of the ejecta relative to the impact speed
+    ejecta_speed: f32,
+}
+
+struct Allocator {
+    capacity: u32,
+    count: atomic<u32>,
+    alive: atomic<u32>,
+    free_count: atomic<u32>,
+    compacted: atomic<u32>,
+    spawn_count: atomic<u32>,
+    next_id: u32,
+}
+
+struct Scheduler {
+    counter: atomic<u32>,
+    // Length of `active_list` for the current substep
+    active_count: u32,
+}
+
+struct IndirectArgs {
+    dispatch_x: u32,
+    dispatch_y: u32,
+    dispatch_z: u32,
+
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+
+    active_dispatch_x: u32,
+    active_dispatch_y: u32,
+    active_dispatch_z: u32,
 }
 
 struct Particle {
@@ -20,27 +89,92 @@ struct Particle {
     velocity: vec2<f32>,
     radius: f32,
     mass: f32,
+    // Low order part of `position`, only used with extended precision
+    position_lo: vec2<f32>,
+    // Timestep level, the particle is integrated every `2^(max_timestep_level - level)` substeps
+    level: u32,
+    // The substep this particle has been integrated up to
+    time: u32,
+    // Packed sRGB color for rendering, `0` colors by velocity
+    color: u32,
+    // Index when uploaded, spawned particles count up from there and a shattered particle keeps
+    // it as its first fragment. The top bit marks selected particles and the one below
+    // the particles of the followed cluster
+    id: u32,
 }
 
+const PARTICLES_PER_WORKGROUP: u32 = 256;
+const MAX_FRAGMENTS: u32 = 8;
+const PI: f32 = 3.14159265;
+
+// Copies the inactive particles and collects the active ones into `active_list`
 @compute
 @workgroup_size(256)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let total_particles = arrayLength(&particles);
+fn schedule(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let index = global_id.x;
-    if index >= total_particles {
+    if index >= atomicLoad(&allocator.count) {
         return;
     }
 
-    var current = particles[index];
-    if current.mass == 0.0 {
-        output[index] = current;
+    // Dead particles are put on the free list by `collect_free` in `alloc.wgsl`
+    let particle = particles[index];
+    if particle.mass == 0.0 {
+        output[index] = particle;
         return;
     }
 
+    if substep == 0u {
+        atomicAdd(&allocator.alive, 1u);
+    }
+
+    // Every particle is synchronized at the start of a frame
+    if substep == 0u || particle.time == substep {
+        let active_index = atomicAdd(&scheduler.counter, 1u);
+        active_list[active_index] = index;
+    } else {
+        output[index] = particle;
+    }
+}
+
+@compute
+@workgroup_size(1)
+fn finish_schedule() {
+    let active_count = atomicLoad(&scheduler.counter);
+    atomicStore(&scheduler.counter, 0u);
+    scheduler.active_count = active_count;
+
+    indirect.active_dispatch_x = (active_count + PARTICLES_PER_WORKGROUP - 1u) / PARTICLES_PER_WORKGROUP;
+    indirect.active_dispatch_y = 1u;
+    indirect.active_dispatch_z = 1u;
+}
+
+@compute
+@workgroup_size(256)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= scheduler.active_count {
+        return;
+    }
+
+    let total_particles = atomicLoad(&allocator.count);
+    let index = active_list[global_id.x];
+    var current = particles[index];
+
+    let substeps = 1u << params.max_timestep_level;
+    let substep_time = params.delta_time / f32(substeps);
+    let level = min(current.level, params.max_timestep_level);
+    let period = substeps >> level;
+    // The part of the frame this particle is advanced by
+    let fraction = f32(period) / f32(substeps);
+
+    let step_time = params.delta_time * fraction;
+
     let pre_vel = current.velocity;
     var offset = vec2<f32>(0.0);
     var gravity_forces = vec2<f32>(0.0);
     var collision_forces = vec2<f32>(0.0);
+    // The earliest predicted contact as a part of `step_time`
+    var time_of_impact = 1.0;
+    var impact_speed = 0.0;
 
     var i: u32 = 0;
     loop {
@@ -56,40 +190,226 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
             continue;
         }
 
-        let oc = other.position - current.position;
+        // Predict inactive particles to the current substep
+        var other_position = other.position;
+        if substep != 0u && other.time != substep {
+            other_position += other.velocity * (f32(substep) - f32(other.time)) * substep_time;
+        }
+
+        var oc = other_position - current.position;
+        if params.extended_precision != 0u {
+            oc += other.position_lo - current.position_lo;
+        }
+
         let rr = current.radius + other.radius;
         let oc_sqr_len = dot(oc, oc);
         let oc_len = sqrt(oc_sqr_len);
-        let normal = oc / oc_len;
+        let direction = oc / oc_len;
         if oc_len <= 1e-8 {
             continue;
         }
 
-        if oc_len < rr {
+        var colliding = oc_len < rr;
+        var contact_normal = direction;
+        if !colliding && params.continuous_collisions != 0u {
+            let impact = sweep_spheres(oc, other.velocity - pre_vel, rr, step_time);
+            if impact < 1.0 {
+                // Resolve the collision at the contact point instead of letting them pass through
+                colliding = true;
+                contact_normal = normalize(oc + (other.velocity - pre_vel) * step_time * impact);
+                time_of_impact = min(time_of_impact, impact);
+            }
+        }
+
+        if colliding {
             // Collision
             let penetration_depth = rr - oc_len;
 
-            let pre_solve_normal_vel = dot(pre_vel - other.velocity, normal);
-            let normal_vel = dot((current.velocity + collision_forces) - other.velocity, normal);
+            let pre_solve_normal_vel = dot(pre_vel - other.velocity, contact_normal);
+            impact_speed = max(impact_speed, pre_solve_normal_vel);
+            let normal_vel = dot((current.velocity + collision_forces) - other.velocity, contact_normal);
             let restitution = 0.4;
 
             let w0 = 1.0 / current.mass;
             let w1 = 1.0 / other.mass;
 
-            offset -= normal * penetration_depth * w0 / (w1 + w0);
-            collision_forces += normal * (-normal_vel - restitution * pre_solve_normal_vel) * w0 / (w1 + w0);
+            offset -= contact_normal * max(penetration_depth, 0.0) * w0 / (w1 + w0);
+            collision_forces += contact_normal * (-normal_vel - restitution * pre_solve_normal_vel) * w0 / (w1 + w0);
         }
-        
+
         // Newtonian
         let force = current.mass * other.mass / max(oc_len, 0.01) * params.gravitational_constant;
-        gravity_forces += normal * force;
+        gravity_forces += direction * force;
 
         continuing {
             i = i + 1u;
         }
     }
 
-    current.velocity += gravity_forces + collision_forces;
-    current.position += offset + current.velocity * params.delta_time;
+    // Gravity is applied per frame, so it's scaled down to the part of the frame this step covers
+    let free_velocity = current.velocity + gravity_forces * fraction;
+    current.velocity = free_velocity + collision_forces;
+
+    // Move with the old velocity up to the earliest contact and with the new one after it
+    let contact = select(0.0, time_of_impact, time_of_impact < 1.0);
+    let delta = offset + mix(current.velocity, free_velocity, contact) * step_time;
+    if params.extended_precision != 0u {
+        let position = extended_add(current.position, current.position_lo, delta);
+        current.position = position.hi;
+        current.position_lo = position.lo;
+    } else {
+        // Folds the low order part in, the readers always add it to the position
+        current.position += current.position_lo + delta;
+        current.position_lo = vec2<f32>(0.0);
+    }
+
+    let time = substep + period;
+    current.level = next_level(current, level, time, gravity_forces);
+    current.time = time;
+
+    if params.fragmentation_speed > 0.0 && impact_speed > params.fragmentation_speed {
+        shatter(&current, index, impact_speed);
+    }
+
     output[index] = current;
 }
+
+// Shatters `particle` into equal fragments, `particle` becomes the first one and the rest
+// are queued for spawning. Mass, momentum and the center of mass are conserved.
+fn shatter(particle: ptr<function, Particle>, index: u32, impact_speed: f32) {
+    let count = clamp(params.fragment_count, 2u, MAX_FRAGMENTS);
+    let mass = (*particle).mass / f32(count);
+    if mass < params.min_fragment_mass {
+        return;
+    }
+
+    let queue_index = reserve_spawn(count - 1u);
+    if queue_index == 0xffffffffu {
+        return;
+    }
+
+    var seed = hash(index ^ hash(bitcast<u32>((*particle).position.x) ^ hash(bitcast<u32>((*particle).position.y))));
+    let radius = (*particle).radius / sqrt(f32(count));
+    // Far enough apart that the fragments don't overlap
+    let ring_radius = radius / sin(PI / f32(count));
+    let rotation = random(&seed) * 2.0 * PI;
+
+    var offsets: array<vec2<f32>, MAX_FRAGMENTS>;
+    var kicks: array<vec2<f32>, MAX_FRAGMENTS>;
+    var mean_offset = vec2<f32>(0.0);
+    var mean_kick = vec2<f32>(0.0);
+    for (var i = 0u; i < count; i++) {
+        let angle = rotation + 2.0 * PI * f32(i) / f32(count);
+        let direction = vec2<f32>(cos(angle), sin(angle));
+        let kick_angle = angle + (random(&seed) - 0.5) * PI / f32(count);
+        let kick_speed = params.ejecta_speed * impact_speed * random(&seed);
+
+        offsets[i] = direction * ring_radius;
+        kicks[i] = vec2<f32>(cos(kick_angle), sin(kick_angle)) * kick_speed;
+        mean_offset += offsets[i];
+        mean_kick += kicks[i];
+    }
+    mean_offset /= f32(count);
+    mean_kick /= f32(count);
+
+    var piece = *particle;
+    piece.mass = mass;
+    piece.radius = radius;
+    for (var i = 1u; i < count; i++) {
+        piece.position = (*particle).position + offsets[i] - mean_offset;
+        piece.velocity = (*particle).velocity + kicks[i] - mean_kick;
+        spawn_queue[queue_index + i - 1u] = piece;
+    }
+
+    (*particle).mass = mass;
+    (*particle).radius = radius;
+    (*particle).position += offsets[0] - mean_offset;
+    (*particle).velocity += kicks[0] - mean_kick;
+}
+
+// Reserves `count` entries in `spawn_queue` if there are enough free slots for them,
+// returns the first entry or `0xffffffff`
+fn reserve_spawn(count: u32) -> u32 {
+    let available = allocator.capacity - atomicLoad(&allocator.count) + atomicLoad(&allocator.free_count);
+
+    // Entries are never given back since `atomicCompareExchangeWeak` doesn't compile to SPIR-V,
+    // the ones of a failed reservation that would still be spawned are marked dead instead
+    let queued = atomicAdd(&allocator.spawn_count, count);
+    if queued + count > available {
+        for (var i = queued; i < min(queued + count, available); i++) {
+            spawn_queue[i].mass = 0.0;
+        }
+        return 0xffffffffu;
+    }
+
+    return queued;
+}
+
+// https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// A random number in `[0, 1)`
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed >> 8u) / 16777216.0;
+}
+
+// Swept sphere test between two spheres `offset` apart with a combined `radius`,
+// returns the time of impact as a part of `duration` or `1.0` if they don't touch
+fn sweep_spheres(offset: vec2<f32>, relative_velocity: vec2<f32>, radius: f32, duration: f32) -> f32 {
+    let movement = relative_velocity * duration;
+
+    // Solve `|offset + movement * t| = radius`
+    let a = dot(movement, movement);
+    let b = dot(offset, movement);
+    let c = dot(offset, offset) - radius * radius;
+    if a <= 1e-12 || b >= 0.0 {
+        // Not moving towards each other
+        return 1.0;
+    }
+
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return 1.0;
+    }
+
+    let t = (-b - sqrt(discriminant)) / a;
+    return select(1.0, t, t >= 0.0 && t < 1.0);
+}
+
+// Picks the level from the acceleration `dt = accuracy * sqrt(radius / |a|)`,
+// coarser levels are only allowed when `time` lines up with their period
+fn next_level(particle: Particle, level: u32, time: u32, gravity_forces: vec2<f32>) -> u32 {
+    let acceleration = length(gravity_forces) / params.delta_time;
+    let ideal_time = params.timestep_accuracy * sqrt(max(particle.radius, 1e-4) / max(acceleration, 1e-12));
+    let ideal_level = ceil(log2(params.delta_time / ideal_time));
+
+    var next = u32(clamp(ideal_level, 0.0, f32(params.max_timestep_level)));
+    let substeps = 1u << params.max_timestep_level;
+    while next < level && time % (substeps >> next) != 0u {
+        next += 1u;
+    }
+
+    return next;
+}
+
+// A double-single number, `hi + lo` with `|lo| <= ulp(hi) / 2`
+struct Extended {
+    hi: vec2<f32>,
+    lo: vec2<f32>,
+}
+
+// Adds `value` to the double-single `hi + lo` without losing the low order bits
+// https://en.wikipedia.org/wiki/2Sum
+fn extended_add(hi: vec2<f32>, lo: vec2<f32>, value: vec2<f32>) -> Extended {
+    let sum = hi + value;
+    let b = sum - hi;
+    let error = (hi - (sum - b)) + (value - b) + lo;
+
+    let result = sum + error;
+    return Extended(result, error - (result - sum));
+}
@@ -0,0 +1,189 @@
+//! Rolling charts of the statistics read back from the GPU
+
+use std::collections::VecDeque;
+
+use egui::Widget;
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+
+use crate::follow::InfoOutput;
+
+/// A chart of the last `history` samples of some lines over the frame they were measured in
+struct Chart {
+    name: &'static str,
+    lines: Vec<(&'static str, VecDeque<[f64; 2]>)>,
+    /// Samples kept of every line
+    history: usize,
+    /// Keeps the samples on screen instead of adding new ones
+    paused: bool,
+    /// Lets the chart be zoomed and dragged instead of fitting all samples
+    zoom: bool,
+}
+
+impl Chart {
+    fn new(name: &'static str, lines: &[&'static str]) -> Self {
+        Self {
+            name,
+            lines: lines.iter().map(|&line| (line, VecDeque::new())).collect(),
+            history: 600,
+            paused: false,
+            zoom: false,
+        }
+    }
+
+    /// Adds the value of every line in `frame`
+    fn push(&mut self, frame: u64, values: &[f64]) {
+        if self.paused {
+            return;
+        }
+
+        for ((_, samples), &value) in self.lines.iter_mut().zip(values) {
+            samples.push_back([frame as f64, value]);
+        }
+        self.trim();
+    }
+
+    /// Drops the samples older than the history
+    fn trim(&mut self) {
+        for (_, samples) in &mut self.lines {
+            let excess = samples.len().saturating_sub(self.history);
+            samples.drain(..excess);
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(self.name, |ui| {
+            ui.horizontal(|ui| {
+                egui::DragValue::new(&mut self.history)
+                    .clamp_range(2..=100_000)
+                    .suffix(" Samples")
+                    .ui(ui);
+                ui.checkbox(&mut self.paused, "Pause");
+                ui.checkbox(&mut self.zoom, "Zoom");
+            });
+            self.trim();
+
+            let mut plot = Plot::new(self.name)
+                .height(120.0)
+                .x_axis_label("Frame")
+                .allow_zoom(self.zoom)
+                .allow_drag(self.zoom)
+                .allow_scroll(self.zoom)
+                .allow_boxed_zoom(self.zoom);
+            if self.lines.len() > 1 {
+                plot = plot.legend(Legend::default());
+            }
+            // Fits the samples again after zooming
+            if !self.zoom {
+                plot = plot.reset();
+            }
+
+            plot.show(ui, |plot_ui| {
+                for (name, samples) in &self.lines {
+                    let points: PlotPoints = samples.iter().copied().collect();
+                    plot_ui.line(Line::new(points).name(name));
+                }
+            });
+        });
+    }
+}
+
+/// The charts of the `Plots` window
+pub struct Plots {
+    /// Reads the statistics back every frame, even when nothing else needs them
+    pub collect: bool,
+
+    center_of_mass: Chart,
+    avg_velocity: Chart,
+    extent: Chart,
+    frame_time: Chart,
+    velocity_sum: Chart,
+    specific_angular_momentum: Chart,
+    momentum: Chart,
+    angular_momentum: Chart,
+    kinetic_energy: Chart,
+    total_mass: Chart,
+}
+
+impl Plots {
+    pub fn new() -> Self {
+        Self {
+            collect: true,
+
+            center_of_mass: Chart::new("Center of Mass", &["x", "y"]),
+            avg_velocity: Chart::new("Average Velocity", &["x", "y"]),
+            extent: Chart::new("Bounds Extent", &["width", "height"]),
+            frame_time: Chart::new("Frame Time (ms)", &["frame time"]),
+            velocity_sum: Chart::new("Velocity Sum", &["x", "y"]),
+            specific_angular_momentum: Chart::new(
+                "Specific Angular Momentum",
+                &["specific angular momentum"],
+            ),
+            momentum: Chart::new("Momentum", &["x", "y"]),
+            angular_momentum: Chart::new("Angular Momentum", &["angular momentum"]),
+            kinetic_energy: Chart::new("Kinetic Energy", &["kinetic energy"]),
+            total_mass: Chart::new("Total Mass", &["total mass"]),
+        }
+    }
+
+    /// Adds the statistics measured in `frame`
+    pub fn push_statistics(&mut self, frame: u64, info: &InfoOutput) {
+        let extent = info.max_position - info.min_position;
+        let momentum = info.center_of_mass_velocity * info.total_mass;
+        let velocity_sum = info.avg_velocity * info.count as f32;
+
+        self.center_of_mass.push(
+            frame,
+            &[info.center_of_mass.x as f64, info.center_of_mass.y as f64],
+        );
+        self.avg_velocity.push(
+            frame,
+            &[info.avg_velocity.x as f64, info.avg_velocity.y as f64],
+        );
+        self.extent.push(frame, &[extent.x as f64, extent.y as f64]);
+        self.velocity_sum
+            .push(frame, &[velocity_sum.x as f64, velocity_sum.y as f64]);
+        self.specific_angular_momentum
+            .push(frame, &[info.specific_angular_momentum as f64]);
+        self.momentum
+            .push(frame, &[momentum.x as f64, momentum.y as f64]);
+        self.angular_momentum
+            .push(frame, &[info.angular_momentum as f64]);
+        self.kinetic_energy
+            .push(frame, &[info.kinetic_energy as f64]);
+        self.total_mass.push(frame, &[info.total_mass as f64]);
+    }
+
+    /// Adds the time `frame` took in seconds
+    pub fn push_frame_time(&mut self, frame: u64, frame_time: f32) {
+        self.frame_time.push(frame, &[frame_time as f64 * 1000.0]);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.collect, "Collect without Following");
+        for chart in [
+            &mut self.center_of_mass,
+            &mut self.avg_velocity,
+            &mut self.extent,
+            &mut self.frame_time,
+        ] {
+            chart.ui(ui);
+        }
+
+        // Gravity changes velocities regardless of the mass, see `InfoOutput::specific_angular_momentum`
+        ui.label("Conserved by Gravity");
+        for chart in [&mut self.velocity_sum, &mut self.specific_angular_momentum] {
+            chart.ui(ui);
+        }
+
+        // Collisions conserve the momentum and the angular momentum, merging particles lose kinetic energy
+        ui.label("Totals");
+        for chart in [
+            &mut self.momentum,
+            &mut self.angular_momentum,
+            &mut self.kinetic_energy,
+            &mut self.total_mass,
+        ] {
+            chart.ui(ui);
+        }
+    }
+}
@@ -0,0 +1,843 @@
+use std::f32::consts::TAU;
+
+use egui::Widget;
+use glam::{DVec2, Vec2};
+use rand::Rng;
+
+use crate::{
+    distribution::{gaussian, ParticleProperties},
+    particle::{Particle, ParticleSet},
+    physics::Gravity,
+};
+
+/// The initial conditions selectable with `--preset`
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PresetKind {
+    Chunks,
+    Plummer,
+    Disk,
+    ColdCollapse,
+    Lattice,
+    RotatingCloud,
+    GalaxyCollision,
+    PlanetarySystem,
+}
+
+impl PresetKind {
+    pub const ALL: [PresetKind; 8] = [
+        PresetKind::Chunks,
+        PresetKind::Plummer,
+        PresetKind::Disk,
+        PresetKind::ColdCollapse,
+        PresetKind::Lattice,
+        PresetKind::RotatingCloud,
+        PresetKind::GalaxyCollision,
+        PresetKind::PlanetarySystem,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PresetKind::Chunks => "Chunks",
+            PresetKind::Plummer => "Plummer Sphere",
+            PresetKind::Disk => "Disk Galaxy",
+            PresetKind::ColdCollapse => "Cold Collapse",
+            PresetKind::Lattice => "Lattice",
+            PresetKind::RotatingCloud => "Rotating Cloud",
+            PresetKind::GalaxyCollision => "Galaxy Collision",
+            PresetKind::PlanetarySystem => "Planetary System",
+        }
+    }
+}
+
+/// Initial conditions with their parameters, in scene files the variant is picked by `kind`
+#[derive(Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Preset {
+    /// Square chunks of 128 particles at rest scattered within `spread` of the origin
+    Chunks { spread: f32 },
+    /// A Plummer sphere seen from above with random velocities in virial equilibrium
+    Plummer { scale_radius: f32 },
+    /// An exponential disk cut off at `max_radius`, every particle is on a circular orbit
+    /// around the mass inside of it
+    Disk { scale_length: f32, max_radius: f32 },
+    /// A uniform disc at rest
+    ColdCollapse { radius: f32 },
+    /// A square grid at rest
+    Lattice { spacing: f32 },
+    /// A uniform disc spinning like a rigid body
+    RotatingCloud { radius: f32, angular_velocity: f32 },
+    /// Two disk galaxies on a collision course
+    GalaxyCollision(GalaxyCollision),
+    /// Bodies on orbits around a star
+    PlanetarySystem(PlanetarySystem),
+}
+
+/// An exponential disk galaxy
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Galaxy {
+    /// Total mass, split evenly between its particles
+    pub mass: f32,
+    pub scale_length: f32,
+    pub max_radius: f32,
+    /// Orbit clockwise instead of counterclockwise
+    pub clockwise: bool,
+}
+
+/// Two galaxies approaching each other along the x axis, centered on the origin
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GalaxyCollision {
+    pub galaxies: [Galaxy; 2],
+    /// The part of the particles in the first galaxy
+    pub split: f32,
+    /// Distance along the x axis at the start
+    pub separation: f32,
+    /// Distance along the y axis, how far they would miss each other without gravity
+    pub impact_parameter: f32,
+    /// Relative speed the galaxies start with
+    pub approach_speed: f32,
+}
+
+impl Default for GalaxyCollision {
+    fn default() -> Self {
+        Self {
+            galaxies: [
+                Galaxy {
+                    mass: 300.0,
+                    scale_length: 4.0,
+                    max_radius: 20.0,
+                    clockwise: false,
+                },
+                Galaxy {
+                    mass: 150.0,
+                    scale_length: 3.0,
+                    max_radius: 15.0,
+                    clockwise: true,
+                },
+            ],
+            split: 0.66,
+            separation: 80.0,
+            impact_parameter: 20.0,
+            approach_speed: 5.0,
+        }
+    }
+}
+
+impl GalaxyCollision {
+    /// Generates both galaxies, the particle masses are scaled to the mass of their galaxy
+    pub fn generate(
+        &self,
+        rng: &mut impl Rng,
+        count: usize,
+        gravity: Gravity,
+        properties: &ParticleProperties,
+    ) -> ParticleSet {
+        let [first, second] = &self.galaxies;
+        let offset = Vec2::new(self.separation, self.impact_parameter);
+        let velocity = Vec2::new(self.approach_speed, 0.0);
+
+        // Every pair pulls both of its particles by the same amount, so the velocities and not
+        // the momenta add up to a constant. Each galaxy is offset by the other's share of the
+        // particles to keep their sum and the mean position at the origin.
+        let first_count = (count as f32 * self.split.clamp(0.0, 1.0)).round() as usize;
+        let first_share = first_count as f32 / count.max(1) as f32;
+        let second_share = 1.0 - first_share;
+
+        let masses = sample_masses(rng, properties, first_count, Some(first.mass));
+        let mut particles = exponential_disk(
+            rng,
+            first,
+            properties,
+            masses,
+            -offset * second_share,
+            velocity * second_share,
+            gravity,
+        );
+        let masses = sample_masses(rng, properties, count - first_count, Some(second.mass));
+        particles.merge(exponential_disk(
+            rng,
+            second,
+            properties,
+            masses,
+            offset * first_share,
+            -velocity * first_share,
+            gravity,
+        ));
+
+        // The random orbits within the disks don't cancel exactly
+        let drift = particles.iter().map(|p| p.velocity).sum::<Vec2>() / count.max(1) as f32;
+        for particle in particles.iter_mut() {
+            particle.velocity -= drift;
+        }
+
+        particles
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        for (galaxy, name) in self
+            .galaxies
+            .iter_mut()
+            .zip(["First Galaxy", "Second Galaxy"])
+        {
+            ui.label(name);
+            ui.add(
+                egui::DragValue::new(&mut galaxy.mass)
+                    .speed(1.0)
+                    .clamp_range(0.01..=f32::MAX)
+                    .prefix("Mass "),
+            );
+            length_ui(ui, &mut galaxy.scale_length, "Scale Length ");
+            length_ui(ui, &mut galaxy.max_radius, "Max Radius ");
+            ui.checkbox(&mut galaxy.clockwise, "Clockwise");
+            ui.add_space(5.0);
+        }
+
+        ui.add(egui::Slider::new(&mut self.split, 0.0..=1.0).text("Particle Split"));
+        length_ui(ui, &mut self.separation, "Separation ");
+        ui.add(
+            egui::DragValue::new(&mut self.impact_parameter)
+                .speed(0.1)
+                .prefix("Impact Parameter "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut self.approach_speed)
+                .speed(0.1)
+                .prefix("Approach Speed "),
+        );
+    }
+}
+
+impl From<PresetKind> for Preset {
+    fn from(kind: PresetKind) -> Self {
+        match kind {
+            PresetKind::Chunks => Preset::Chunks { spread: 20.0 },
+            PresetKind::Plummer => Preset::Plummer { scale_radius: 5.0 },
+            PresetKind::Disk => Preset::Disk {
+                scale_length: 5.0,
+                max_radius: 25.0,
+            },
+            PresetKind::ColdCollapse => Preset::ColdCollapse { radius: 20.0 },
+            PresetKind::Lattice => Preset::Lattice { spacing: 0.5 },
+            PresetKind::RotatingCloud => Preset::RotatingCloud {
+                radius: 20.0,
+                angular_velocity: 0.1,
+            },
+            PresetKind::GalaxyCollision => Preset::GalaxyCollision(GalaxyCollision::default()),
+            PresetKind::PlanetarySystem => Preset::PlanetarySystem(PlanetarySystem::default()),
+        }
+    }
+}
+
+impl Preset {
+    pub fn kind(&self) -> PresetKind {
+        match self {
+            Preset::Chunks { .. } => PresetKind::Chunks,
+            Preset::Plummer { .. } => PresetKind::Plummer,
+            Preset::Disk { .. } => PresetKind::Disk,
+            Preset::ColdCollapse { .. } => PresetKind::ColdCollapse,
+            Preset::Lattice { .. } => PresetKind::Lattice,
+            Preset::RotatingCloud { .. } => PresetKind::RotatingCloud,
+            Preset::GalaxyCollision(_) => PresetKind::GalaxyCollision,
+            Preset::PlanetarySystem(_) => PresetKind::PlanetarySystem,
+        }
+    }
+
+    /// Generates `count` particles with masses and radii drawn from `properties`,
+    /// velocities are derived from `gravity`. Explicitly given bodies keep their own.
+    pub fn generate(
+        &self,
+        count: usize,
+        gravity: Gravity,
+        properties: &ParticleProperties,
+    ) -> ParticleSet {
+        let mut rng = rand::thread_rng();
+        let rng = &mut rng;
+
+        match *self {
+            Preset::Chunks { spread } => chunks(rng, properties, count, spread),
+            Preset::Plummer { scale_radius } => {
+                plummer(rng, properties, count, scale_radius, gravity)
+            }
+            Preset::Disk {
+                scale_length,
+                max_radius,
+            } => {
+                let masses = sample_masses(rng, properties, count, None);
+                let galaxy = Galaxy {
+                    mass: masses.iter().sum(),
+                    scale_length,
+                    max_radius,
+                    clockwise: false,
+                };
+                exponential_disk(
+                    rng,
+                    &galaxy,
+                    properties,
+                    masses,
+                    Vec2::ZERO,
+                    Vec2::ZERO,
+                    gravity,
+                )
+            }
+            Preset::ColdCollapse { radius } => (0..count)
+                .map(|_| {
+                    let position = uniform_disc(rng, radius);
+                    sample(rng, properties, position, Vec2::ZERO)
+                })
+                .collect(),
+            Preset::Lattice { spacing } => lattice(rng, properties, count, spacing),
+            Preset::RotatingCloud {
+                radius,
+                angular_velocity,
+            } => (0..count)
+                .map(|_| {
+                    let position = uniform_disc(rng, radius);
+                    sample(
+                        rng,
+                        properties,
+                        position,
+                        position.perp() * angular_velocity,
+                    )
+                })
+                .collect(),
+            Preset::GalaxyCollision(collision) => {
+                collision.generate(rng, count, gravity, properties)
+            }
+            Preset::PlanetarySystem(ref system) => system.generate(rng, count, gravity),
+        }
+    }
+
+    /// Edits the parameters of this preset
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let mut kind = self.kind();
+        egui::ComboBox::from_label("Preset")
+            .selected_text(kind.name())
+            .show_ui(ui, |ui| {
+                for option in PresetKind::ALL {
+                    ui.selectable_value(&mut kind, option, option.name());
+                }
+            });
+        if kind != self.kind() {
+            *self = kind.into();
+        }
+
+        match self {
+            Preset::Chunks { spread } => length_ui(ui, spread, "Spread "),
+            Preset::Plummer { scale_radius } => length_ui(ui, scale_radius, "Scale Radius "),
+            Preset::Disk {
+                scale_length,
+                max_radius,
+            } => {
+                length_ui(ui, scale_length, "Scale Length ");
+                length_ui(ui, max_radius, "Max Radius ");
+            }
+            Preset::ColdCollapse { radius } => length_ui(ui, radius, "Radius "),
+            Preset::Lattice { spacing } => length_ui(ui, spacing, "Spacing "),
+            Preset::RotatingCloud {
+                radius,
+                angular_velocity,
+            } => {
+                length_ui(ui, radius, "Radius ");
+                ui.add(
+                    egui::DragValue::new(angular_velocity)
+                        .speed(0.01)
+                        .prefix("Angular Velocity "),
+                );
+            }
+            Preset::GalaxyCollision(collision) => collision.ui(ui),
+            Preset::PlanetarySystem(system) => system.ui(ui),
+        }
+    }
+}
+
+/// A body given by its orbital elements
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Orbit {
+    pub semi_major_axis: f32,
+    #[serde(default)]
+    pub eccentricity: f32,
+    /// Angle of the periapsis from the x axis in radians
+    #[serde(default)]
+    pub argument_of_periapsis: f32,
+    /// Where on its orbit the body starts in radians, `0` is the periapsis
+    #[serde(default)]
+    pub mean_anomaly: f32,
+    pub mass: f32,
+    pub radius: f32,
+}
+
+impl Orbit {
+    /// Position and velocity relative to a star of `star_mass`, the star moves the opposite way
+    /// at the same speed
+    fn state(&self, star_mass: f32, gravity: Gravity) -> (Vec2, Vec2) {
+        let eccentricity = self.eccentricity.clamp(0.0, 0.99);
+        let periapsis = self.semi_major_axis * (1.0 - eccentricity);
+        let apoapsis = self.semi_major_axis * (1.0 + eccentricity);
+
+        // Kepler's equation `M = E - e sin(E)`
+        let mut eccentric_anomaly = self.mean_anomaly;
+        for _ in 0..16 {
+            eccentric_anomaly -=
+                (eccentric_anomaly - eccentricity * eccentric_anomaly.sin() - self.mean_anomaly)
+                    / (1.0 - eccentricity * eccentric_anomaly.cos());
+        }
+        let true_anomaly = 2.0
+            * f32::atan2(
+                (1.0 + eccentricity).sqrt() * (eccentric_anomaly / 2.0).sin(),
+                (1.0 - eccentricity).sqrt() * (eccentric_anomaly / 2.0).cos(),
+            );
+        let distance = self.semi_major_axis * (1.0 - eccentricity * eccentric_anomaly.cos());
+
+        let direction = Vec2::from_angle(self.argument_of_periapsis + true_anomaly);
+        let velocity =
+            gravity.orbital_velocity(self.mass, star_mass, periapsis, apoapsis, distance);
+        // Moving away from the star between the periapsis and the apoapsis
+        let radial = if true_anomaly.sin() >= 0.0 {
+            velocity.x
+        } else {
+            -velocity.x
+        };
+
+        // The velocity relative to the star is split evenly between them
+        (
+            direction * distance,
+            (direction * radial + direction.perp() * velocity.y) / 2.0,
+        )
+    }
+}
+
+/// Small bodies on circular orbits in a ring around the star
+#[derive(Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Belt {
+    /// Distance of the middle of the belt from the star
+    pub radius: f32,
+    pub width: f32,
+    pub count: u32,
+    pub mass: f32,
+    pub particle_radius: f32,
+}
+
+impl Default for Belt {
+    fn default() -> Self {
+        Self {
+            radius: 24.0,
+            width: 3.0,
+            count: 1000,
+            mass: 0.001,
+            particle_radius: 0.05,
+        }
+    }
+}
+
+/// A star at the origin with bodies on orbits around it
+#[derive(Clone, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlanetarySystem {
+    pub star_mass: f32,
+    pub star_radius: f32,
+    /// Scene files start without bodies or a belt
+    #[serde(default)]
+    pub bodies: Vec<Orbit>,
+    #[serde(default)]
+    pub belt: Option<Belt>,
+}
+
+impl Default for PlanetarySystem {
+    fn default() -> Self {
+        let planet = |semi_major_axis, eccentricity, mean_anomaly, mass, radius| Orbit {
+            semi_major_axis,
+            eccentricity,
+            argument_of_periapsis: 0.0,
+            mean_anomaly,
+            mass,
+            radius,
+        };
+
+        Self {
+            star_mass: 1000.0,
+            star_radius: 1.0,
+            bodies: vec![
+                planet(10.0, 0.05, 0.0, 1.0, 0.3),
+                planet(18.0, 0.1, 2.0, 2.0, 0.4),
+                planet(32.0, 0.2, 4.0, 0.5, 0.25),
+            ],
+            belt: Some(Belt::default()),
+        }
+    }
+}
+
+impl PlanetarySystem {
+    /// Generates the star, its bodies and as much of the belt as fits into `count` particles
+    pub fn generate(&self, rng: &mut impl Rng, count: usize, gravity: Gravity) -> ParticleSet {
+        let mut particles = ParticleSet::from(vec![Particle::new(
+            DVec2::ZERO,
+            Vec2::ZERO,
+            self.star_radius,
+            self.star_mass,
+        )]);
+
+        for body in &self.bodies {
+            let (position, velocity) = body.state(self.star_mass, gravity);
+            particles.push(Particle::new(
+                position.as_dvec2(),
+                velocity,
+                body.radius,
+                body.mass,
+            ));
+        }
+
+        if let Some(belt) = &self.belt {
+            let belt_count = (belt.count as usize).min(count.saturating_sub(particles.len()));
+            for _ in 0..belt_count {
+                let direction = random_direction(rng);
+                let radius = belt.radius + rng.gen_range(-0.5..=0.5) * belt.width;
+                let speed = gravity.circular_speed(belt.mass, self.star_mass, radius);
+
+                particles.push(Particle::new(
+                    (direction * radius).as_dvec2(),
+                    direction.perp() * speed,
+                    belt.particle_radius,
+                    belt.mass,
+                ));
+            }
+        }
+
+        // Every pair pulls both of its particles by the same amount, so the velocities and not
+        // the momenta add up to a constant. The star cancels them to keep the system in place.
+        particles.truncate(count);
+        if !particles.is_empty() {
+            let drift: Vec2 = particles[1..].iter().map(|p| p.velocity).sum();
+            particles[0].velocity = -drift;
+        }
+
+        particles
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::DragValue::new(&mut self.star_mass)
+                .speed(1.0)
+                .clamp_range(0.01..=f32::MAX)
+                .prefix("Star Mass "),
+        );
+        length_ui(ui, &mut self.star_radius, "Star Radius ");
+
+        let mut removed = None;
+        for (i, body) in self.bodies.iter_mut().enumerate() {
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                ui.label(format!("Body {}", i + 1));
+                if ui.small_button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+            length_ui(ui, &mut body.semi_major_axis, "Semi-major Axis ");
+            ui.add(egui::Slider::new(&mut body.eccentricity, 0.0..=0.99).text("Eccentricity"));
+            ui.horizontal(|ui| {
+                ui.label("Argument of Periapsis");
+                ui.drag_angle(&mut body.argument_of_periapsis);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Mean Anomaly");
+                ui.drag_angle(&mut body.mean_anomaly);
+            });
+            ui.add(
+                egui::DragValue::new(&mut body.mass)
+                    .speed(0.01)
+                    .clamp_range(0.0001..=f32::MAX)
+                    .prefix("Mass "),
+            );
+            length_ui(ui, &mut body.radius, "Radius ");
+        }
+        if let Some(i) = removed {
+            self.bodies.remove(i);
+        }
+
+        if ui.button("Add Body").clicked() {
+            let semi_major_axis = self.bodies.last().map_or(10.0, |b| b.semi_major_axis * 1.5);
+            self.bodies.push(Orbit {
+                semi_major_axis,
+                eccentricity: 0.0,
+                argument_of_periapsis: 0.0,
+                mean_anomaly: 0.0,
+                mass: 1.0,
+                radius: 0.3,
+            });
+        }
+
+        ui.add_space(5.0);
+        let mut has_belt = self.belt.is_some();
+        if ui.checkbox(&mut has_belt, "Asteroid Belt").changed() {
+            self.belt = has_belt.then(Belt::default);
+        }
+        if let Some(belt) = &mut self.belt {
+            length_ui(ui, &mut belt.radius, "Radius ");
+            length_ui(ui, &mut belt.width, "Width ");
+            egui::DragValue::new(&mut belt.count)
+                .suffix(" Asteroids")
+                .ui(ui);
+            ui.add(
+                egui::DragValue::new(&mut belt.mass)
+                    .speed(0.0001)
+                    .clamp_range(0.0001..=f32::MAX)
+                    .prefix("Asteroid Mass "),
+            );
+            length_ui(ui, &mut belt.particle_radius, "Asteroid Radius ");
+        }
+    }
+}
+
+fn length_ui(ui: &mut egui::Ui, value: &mut f32, prefix: &str) {
+    ui.add(
+        egui::DragValue::new(value)
+            .speed(0.1)
+            .clamp_range(0.01..=10000.0)
+            .prefix(prefix),
+    );
+}
+
+/// A particle with a mass and radius drawn from `properties`
+fn sample(
+    rng: &mut impl Rng,
+    properties: &ParticleProperties,
+    position: Vec2,
+    velocity: Vec2,
+) -> Particle {
+    let mass = properties.sample_mass(rng);
+    let radius = properties.radius(rng, mass);
+    Particle::new(position.as_dvec2(), velocity, radius, mass)
+}
+
+/// Draws `count` masses, scaled to add up to `total_mass` if given
+fn sample_masses(
+    rng: &mut impl Rng,
+    properties: &ParticleProperties,
+    count: usize,
+    total_mass: Option<f32>,
+) -> Vec<f32> {
+    let mut masses: Vec<f32> = (0..count).map(|_| properties.sample_mass(rng)).collect();
+    if let Some(total_mass) = total_mass {
+        let scale = total_mass / masses.iter().sum::<f32>().max(f32::MIN_POSITIVE);
+        masses.iter_mut().for_each(|mass| *mass *= scale);
+    }
+
+    masses
+}
+
+fn chunks(
+    rng: &mut impl Rng,
+    properties: &ParticleProperties,
+    count: usize,
+    spread: f32,
+) -> ParticleSet {
+    let mut particles = ParticleSet::with_capacity(count);
+    while particles.len() < count {
+        let chunk = Vec2::new(
+            rng.gen_range(-spread..=spread),
+            rng.gen_range(-spread..=spread),
+        );
+        for _ in 0..128.min(count - particles.len()) {
+            let dir = Vec2::new(rng.gen_range(-1f32..=1f32), rng.gen_range(-1f32..=1f32));
+            let d = rng.gen_range(0.0..=4.0);
+            particles.push(sample(rng, properties, chunk + dir * d, Vec2::ZERO));
+        }
+    }
+
+    particles
+}
+
+/// The surface density of a Plummer sphere is `(1 + r^2 / a^2)^-2`
+fn plummer(
+    rng: &mut impl Rng,
+    properties: &ParticleProperties,
+    count: usize,
+    scale_radius: f32,
+    gravity: Gravity,
+) -> ParticleSet {
+    let masses = sample_masses(rng, properties, count, None);
+    let total_mass: f32 = masses.iter().sum();
+
+    let mut particles: ParticleSet = masses
+        .into_iter()
+        .map(|mass| {
+            // Inverse of the enclosed mass `r^2 / (r^2 + a^2)`, the far tail is cut off
+            let u: f32 = rng.gen_range(0.0..0.99);
+            let radius = scale_radius * (u / (1.0 - u)).sqrt();
+            let position = random_direction(rng) * radius;
+            // Split evenly between both axes
+            let sigma = (gravity.virial_speed_squared(mass, total_mass) / 2.0).sqrt();
+            let velocity = Vec2::new(gaussian(rng), gaussian(rng)) * sigma;
+
+            Particle::new(
+                position.as_dvec2(),
+                velocity,
+                properties.radius(rng, mass),
+                mass,
+            )
+        })
+        .collect();
+
+    remove_drift(&mut particles);
+    particles
+}
+
+/// The surface density of an exponential disk is `e^(-r / h)`,
+/// the disk is centered on `center` and moves with `drift`
+fn exponential_disk(
+    rng: &mut impl Rng,
+    galaxy: &Galaxy,
+    properties: &ParticleProperties,
+    masses: Vec<f32>,
+    center: Vec2,
+    drift: Vec2,
+    gravity: Gravity,
+) -> ParticleSet {
+    let spin = if galaxy.clockwise { -1.0 } else { 1.0 };
+
+    let mut radii: Vec<f32> = (0..masses.len())
+        .map(|_| loop {
+            // `r e^(-r / h)` is a gamma distribution with a shape of 2
+            let u: f32 = rng.gen_range(f32::EPSILON..=1.0);
+            let v: f32 = rng.gen_range(f32::EPSILON..=1.0);
+            let radius = -galaxy.scale_length * (u * v).ln();
+            if radius <= galaxy.max_radius {
+                break radius;
+            }
+        })
+        .collect();
+    radii.sort_by(f32::total_cmp);
+
+    let mut enclosed_mass = 0.0;
+    radii
+        .into_iter()
+        .zip(masses)
+        .map(|(radius, mass)| {
+            let direction = random_direction(rng);
+            let speed = gravity.circular_speed(mass, enclosed_mass, radius);
+            enclosed_mass += mass;
+
+            Particle::new(
+                (center + direction * radius).as_dvec2(),
+                drift + direction.perp() * speed * spin,
+                properties.radius(rng, mass),
+                mass,
+            )
+        })
+        .collect()
+}
+
+fn lattice(
+    rng: &mut impl Rng,
+    properties: &ParticleProperties,
+    count: usize,
+    spacing: f32,
+) -> ParticleSet {
+    if count == 0 {
+        return ParticleSet::new();
+    }
+
+    let side = (count as f32).sqrt().ceil() as usize;
+    let center = (side - 1) as f32 / 2.0;
+
+    (0..count)
+        .map(|i| {
+            let cell = Vec2::new((i % side) as f32, (i / side) as f32);
+            sample(rng, properties, (cell - center) * spacing, Vec2::ZERO)
+        })
+        .collect()
+}
+
+fn uniform_disc(rng: &mut impl Rng, radius: f32) -> Vec2 {
+    random_direction(rng) * radius * rng.gen::<f32>().sqrt()
+}
+
+fn random_direction(rng: &mut impl Rng) -> Vec2 {
+    Vec2::from_angle(rng.gen_range(0.0..TAU))
+}
+
+/// Moves into the center of mass frame so the cluster doesn't drift away
+fn remove_drift(particles: &mut ParticleSet) {
+    let count = particles.len().max(1) as f32;
+    let velocity = particles.iter().map(|p| p.velocity).sum::<Vec2>() / count;
+    particles.boost(-velocity);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    const GRAVITY: Gravity = Gravity {
+        gravitational_constant: 0.1,
+        delta_time: 1.0 / 60.0,
+    };
+
+    #[test]
+    fn galaxy_collision_keeps_velocity_sum() {
+        let collision = GalaxyCollision {
+            split: 0.8,
+            ..GalaxyCollision::default()
+        };
+        let particles = collision.generate(
+            &mut StdRng::seed_from_u64(1),
+            1000,
+            GRAVITY,
+            &ParticleProperties::default(),
+        );
+
+        assert_eq!(particles.len(), 1000);
+        let velocity_sum: Vec2 = particles.iter().map(|p| p.velocity).sum();
+        assert!(velocity_sum.length() < 1e-2, "{velocity_sum}");
+    }
+
+    /// Integrates a star and a planet with the force law of `physics.wgsl` and checks that the
+    /// planet stays between the periapsis and apoapsis it was placed on
+    #[test]
+    fn planet_reaches_its_apsides() {
+        let body = Orbit {
+            semi_major_axis: 10.0,
+            eccentricity: 0.3,
+            argument_of_periapsis: 1.0,
+            mean_anomaly: 2.0,
+            mass: 20.0,
+            radius: 0.3,
+        };
+        let system = PlanetarySystem {
+            star_mass: 1000.0,
+            star_radius: 1.0,
+            bodies: vec![body],
+            belt: None,
+        };
+        let particles = system.generate(&mut StdRng::seed_from_u64(1), 2, GRAVITY);
+        assert_eq!(particles[0].velocity, -particles[1].velocity);
+
+        let [mut star, mut planet] = [0, 1].map(|i| {
+            let particle = &particles[i];
+            (particle.position(), particle.velocity.as_dvec2())
+        });
+        let acceleration = GRAVITY.acceleration(system.star_mass, body.mass, 1.0) as f64;
+
+        // Leapfrog over a few revolutions, one takes about a tenth of a second
+        let step = 1e-5;
+        let (mut min_distance, mut max_distance) = (f64::MAX, 0.0f64);
+        for _ in 0..100_000 {
+            let offset = planet.0 - star.0;
+            let pull = offset * (acceleration / offset.length_squared());
+            planet.1 -= pull * step;
+            star.1 += pull * step;
+            planet.0 += planet.1 * step;
+            star.0 += star.1 * step;
+
+            let distance = (planet.0 - star.0).length();
+            min_distance = min_distance.min(distance);
+            max_distance = max_distance.max(distance);
+        }
+
+        assert!((min_distance - 7.0).abs() < 0.01, "{min_distance}");
+        assert!((max_distance - 13.0).abs() < 0.01, "{max_distance}");
+    }
+}
@@ -0,0 +1,120 @@
+//! Reads GPU buffers back without stalling the CPU on the GPU
+
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use log::error;
+
+enum Slot {
+    Free,
+    /// A copy into the staging buffer is encoded but not yet submitted
+    Copied {
+        frame: u64,
+    },
+    Mapping {
+        frame: u64,
+        mapped: Receiver<Result<(), wgpu::BufferAsyncError>>,
+    },
+}
+
+/// A ring of staging buffers. Data copied into one arrives a frame or two later
+/// together with the index of the frame it was copied in, in the order of the frames.
+pub struct Readback {
+    buffers: Vec<wgpu::Buffer>,
+    slots: Vec<Slot>,
+}
+
+impl Readback {
+    pub fn new(device: &wgpu::Device, label: &str, size: u64, slots: usize) -> Self {
+        Self {
+            buffers: (0..slots)
+                .map(|_| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(label),
+                        size,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    })
+                })
+                .collect(),
+            slots: (0..slots).map(|_| Slot::Free).collect(),
+        }
+    }
+
+    /// Every staging buffer is waiting for its data
+    pub fn is_full(&self) -> bool {
+        !self.slots.iter().any(|slot| matches!(slot, Slot::Free))
+    }
+
+    /// A free staging buffer to copy the data of `frame` into, `None` when all are in flight
+    pub fn slot(&mut self, frame: u64) -> Option<&wgpu::Buffer> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| matches!(slot, Slot::Free))?;
+        self.slots[index] = Slot::Copied { frame };
+        Some(&self.buffers[index])
+    }
+
+    /// Starts mapping the staging buffers copied into since the last call,
+    /// has to be called once the copies are submitted
+    pub fn map_copies(&mut self) {
+        for (slot, buffer) in self.slots.iter_mut().zip(&self.buffers) {
+            if let Slot::Copied { frame } = *slot {
+                let (tx, rx) = std::sync::mpsc::sync_channel(1);
+                // The ring may be dropped before the mapping finishes
+                buffer.slice(..).map_async(wgpu::MapMode::Read, move |v| {
+                    let _ = tx.send(v);
+                });
+                *slot = Slot::Mapping { frame, mapped: rx };
+            }
+        }
+    }
+
+    /// Calls `receive` with the frame and data of every staging buffer that arrived,
+    /// oldest first, without waiting for the others
+    pub fn receive(&mut self, device: &wgpu::Device, mut receive: impl FnMut(u64, &[u8])) {
+        device.poll(wgpu::Maintain::Poll);
+
+        while let Some(index) = self.oldest() {
+            let Slot::Mapping { frame, mapped } = &self.slots[index] else {
+                unreachable!()
+            };
+
+            match mapped.try_recv() {
+                Ok(Ok(())) => {
+                    let buffer = &self.buffers[index];
+                    receive(*frame, &buffer.slice(..).get_mapped_range());
+                    buffer.unmap();
+                }
+                Ok(Err(err)) => error!("Failed to read frame {frame} back from the GPU: {err}"),
+                // Later frames can't have arrived before this one
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    error!("Lost the readback of frame {frame}")
+                }
+            }
+            self.slots[index] = Slot::Free;
+        }
+    }
+
+    /// Like [`Self::receive`], but blocks until every staging buffer in flight arrived
+    pub fn wait(&mut self, device: &wgpu::Device, receive: impl FnMut(u64, &[u8])) {
+        if self.oldest().is_some() {
+            device.poll(wgpu::Maintain::wait()).panic_on_timeout();
+        }
+        self.receive(device, receive);
+    }
+
+    /// The slot being mapped with the earliest frame
+    fn oldest(&self) -> Option<usize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Mapping { frame, .. } => Some((index, *frame)),
+                _ => None,
+            })
+            .min_by_key(|(_, frame)| *frame)
+            .map(|(index, _)| index)
+    }
+}
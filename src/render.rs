@@ -2,6 +2,8 @@ use std::borrow::Cow;
 
 use wgpu::{util::DeviceExt, BindGroupLayoutEntry};
 
+use crate::{particle::Particle, physics::DRAW_INDIRECT_OFFSET, utils::split_f64};
+
 pub struct RenderModule {
     pub screen_size_buffer: wgpu::Buffer,
     pub viewport_buffer: wgpu::Buffer,
@@ -26,9 +28,10 @@ impl RenderModule {
                 | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
+        // [offset_x, offset_y, zoom, padding, offset_lo_x, offset_lo_y, color_by_group, padding]
         let viewport_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: 4 * 4,
+            size: 8 * 4,
             usage: wgpu::BufferUsages::VERTEX
                 | wgpu::BufferUsages::UNIFORM
                 | wgpu::BufferUsages::COPY_DST,
@@ -94,15 +97,21 @@ impl RenderModule {
                 entry_point: "vertex",
                 buffers: &[
                     wgpu::VertexBufferLayout {
-                        array_stride: 6 * 4,
+                        array_stride: std::mem::size_of::<Particle>() as u64,
                         step_mode: wgpu::VertexStepMode::Instance,
-                        attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32, 3 => Float32],
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32, 3 => Float32, 5 => Float32x2, 6 => Uint32, 7 => Uint32, 8 => Uint32, 9 => Uint32],
                     },
                     wgpu::VertexBufferLayout {
                         array_stride: 2 * 4,
                         step_mode: wgpu::VertexStepMode::Vertex,
                         attributes: &wgpu::vertex_attr_array![4 => Float32x2],
                     },
+                    // The group labels of `GroupModule`
+                    wgpu::VertexBufferLayout {
+                        array_stride: 4,
+                        step_mode: wgpu::VertexStepMode::Instance,
+                        attributes: &wgpu::vertex_attr_array![10 => Uint32],
+                    },
                 ],
             },
             fragment: Some(wgpu::FragmentState {
@@ -131,7 +140,8 @@ impl RenderModule {
         encoder: &'a mut wgpu::CommandEncoder,
         view: &'a wgpu::TextureView,
         particle_buffer: &'a wgpu::Buffer,
-        num_particles: u32,
+        label_buffer: &'a wgpu::Buffer,
+        indirect_buffer: &'a wgpu::Buffer,
     ) -> wgpu::RenderPass<'a> {
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: None,
@@ -152,7 +162,8 @@ impl RenderModule {
         rpass.set_bind_group(0, &self.bind_group, &[]);
         rpass.set_vertex_buffer(0, particle_buffer.slice(..));
         rpass.set_vertex_buffer(1, self.vertices_buffer.slice(..));
-        rpass.draw(0..3, 0..num_particles);
+        rpass.set_vertex_buffer(2, label_buffer.slice(..));
+        rpass.draw_indirect(indirect_buffer, DRAW_INDIRECT_OFFSET);
 
         rpass
     }
@@ -165,32 +176,24 @@ impl RenderModule {
         );
     }
 
-    pub fn update_offset(&self, queue: &wgpu::Queue, x: f32, y: f32) {
+    pub fn update_offset(&self, queue: &wgpu::Queue, x: f64, y: f64) {
+        let (x, x_lo) = split_f64(x);
+        let (y, y_lo) = split_f64(y);
+
         queue.write_buffer(&self.viewport_buffer, 0, bytemuck::bytes_of(&[x, y]));
+        queue.write_buffer(&self.viewport_buffer, 16, bytemuck::bytes_of(&[x_lo, y_lo]));
     }
 
     pub fn update_zoom(&self, queue: &wgpu::Queue, zoom: f32) {
         queue.write_buffer(&self.viewport_buffer, 8, bytemuck::bytes_of(&[zoom]));
     }
 
-    pub fn update_all(
-        &self,
-        queue: &wgpu::Queue,
-        width: u32,
-        height: u32,
-        x: f32,
-        y: f32,
-        zoom: f32,
-    ) {
-        queue.write_buffer(
-            &self.screen_size_buffer,
-            0,
-            bytemuck::bytes_of(&[width as f32, height as f32]),
-        );
+    /// Colors particles by their friends-of-friends group instead of their color or velocity
+    pub fn update_color_by_group(&self, queue: &wgpu::Queue, color_by_group: bool) {
         queue.write_buffer(
             &self.viewport_buffer,
-            0,
-            bytemuck::bytes_of(&[x, y, zoom, 0f32]),
+            24,
+            bytemuck::bytes_of(&(color_by_group as u32)),
         );
     }
 }
@@ -6,16 +6,23 @@ var<uniform> screen_size: vec2<f32>;
 @binding(1)
 var<uniform> view: View;
 
+// `offset + offset_lo` is a double-single camera offset so particles far away
+// from the origin are positioned relative to the camera without losing precision
 struct View {
     offset: vec2<f32>,
     zoom: f32,
+    offset_lo: vec2<f32>,
+    // Colors particles by `group` instead of their color or velocity
+    color_by_group: u32,
 }
 
+const NO_GROUP: u32 = 0xffffffffu;
+
 struct VertexOutput {
     @builtin(position) coord_in: vec4<f32>,
     @location(0) position: vec2<f32>,
     @location(1) radius: f32,
-    @location(2) velocity: vec2<f32>,
+    @location(2) color: vec3<f32>,
 }
 
 @vertex
@@ -26,6 +33,10 @@ fn vertex(
     @location(2) _particle_radius: f32,
     @location(3) particle_mass: f32,
     @location(4) position: vec2<f32>,
+    @location(5) _particle_position_lo: vec2<f32>,
+    @location(8) particle_color: u32,
+    @location(9) particle_id: u32,
+    @location(10) group: u32,
 ) -> VertexOutput {
     if particle_mass == 0.0 {
         return VertexOutput();
@@ -38,12 +49,29 @@ fn vertex(
     //     pos.x * sin(angle) + pos.y * cos(angle),
     // );
 
-    let particle_position = (_particle_position + view.offset) * view.zoom;
+    let relative_position = (_particle_position + view.offset) + (_particle_position_lo + view.offset_lo);
+    let particle_position = relative_position * view.zoom;
     let particle_radius = _particle_radius * view.zoom * 1.7;
     pos = pos * particle_radius + particle_position;
 
     var result: VertexOutput;
-    result.velocity = particle_velocity;
+    if particle_color == 0u {
+        result.color = aces_tone_map(vec3<f32>(abs(particle_velocity * 0.1) * 0.9 + 0.1, 0.1));
+    } else {
+        result.color = srgb_to_linear(unpack4x8unorm(particle_color).rgb);
+    }
+    // Every group gets a random bright color, particles outside of groups are dimmed
+    if view.color_by_group != 0u {
+        if group == NO_GROUP {
+            result.color = vec3<f32>(0.02);
+        } else {
+            result.color = srgb_to_linear(unpack4x8unorm(hash(group)).rgb * 0.8 + 0.2);
+        }
+    }
+    // Selected particles are tinted yellow
+    if (particle_id & 0x80000000u) != 0u {
+        result.color = mix(result.color, vec3<f32>(1.0, 0.8, 0.0), 0.7);
+    }
     result.radius = particle_radius;
     result.position = particle_position;
     result.coord_in = vec4<f32>((pos * 500) / screen_size, 0.0, 1.0);
@@ -66,9 +94,18 @@ fn fragment(result: VertexOutput) -> @location(0) vec4<f32> {
         // return vec4<f32>(1.0);
     }
 
-    var color = vec3<f32>(abs(result.velocity * 0.1) * 0.9 + 0.1, 0.1);
-    color = aces_tone_map(color);
-    return vec4<f32>(color, 1.0);
+    return vec4<f32>(result.color, 1.0);
+}
+
+// https://www.pcg-random.org/
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn srgb_to_linear(srgb: vec3<f32>) -> vec3<f32> {
+    return select(pow((srgb + 0.055) / 1.055, vec3<f32>(2.4)), srgb / 12.92, srgb <= vec3<f32>(0.04045));
 }
 
 
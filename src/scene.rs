@@ -0,0 +1,463 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use glam::{DVec2, Vec2};
+
+use crate::{
+    csv,
+    distribution::{Distribution, ParticleProperties, Radius},
+    emitter::Emitter,
+    image::ImageGenerator,
+    particle::{Particle, ParticleSet},
+    physics::{Gravity, MAX_FRAGMENTS, MAX_TIMESTEP_LEVEL},
+    preset::Preset,
+};
+
+/// A scene file describing the physics parameters, the camera, the particles and the emitters
+///
+/// ```toml
+/// [physics]
+/// gravity = 0.1
+///
+/// [camera]
+/// center = [0.0, 0.0]
+/// zoom = 0.05
+///
+/// [[component]]
+/// type = "generator"
+/// count = 2048
+/// mass = "power-law:0.01,1"
+/// preset = { kind = "disk", scale_length = 5.0, max_radius = 25.0 }
+/// transform = { offset = [-30.0, 0.0], velocity = [2.0, 0.0] }
+///
+/// [[component]]
+/// type = "body"
+/// position = [0.0, 0.0]
+/// mass = 100.0
+/// radius = 1.0
+///
+/// [[component]]
+/// type = "csv"
+/// path = "example.csv"
+///
+/// [[component]]
+/// type = "image"
+/// path = "logo.png"
+/// generator = { threshold = 0.5, scale = 0.05, velocity = { kind = "rotation", angular_velocity = 0.2 } }
+///
+/// [[emitter]]
+/// shape = { kind = "ring", radius = 40.0 }
+/// direction = 90.0
+/// speed = "uniform:1,2"
+/// rate = 300.0
+/// ```
+pub struct Scene {
+    pub physics: PhysicsSettings,
+    pub camera: CameraSettings,
+    pub components: Vec<Component>,
+    pub emitters: Vec<Emitter>,
+
+    /// The line every component starts at
+    lines: Vec<usize>,
+
+    /// The directory relative paths are resolved from
+    directory: PathBuf,
+}
+
+/// Components are parsed one by one, the `type` tag keeps serde from reporting where they are
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    physics: Option<toml::Spanned<PhysicsSettings>>,
+    #[serde(default)]
+    camera: CameraSettings,
+    #[serde(default, rename = "component")]
+    components: Vec<toml::Spanned<toml::Table>>,
+    #[serde(default, rename = "emitter")]
+    emitters: Vec<Emitter>,
+}
+
+/// Overrides the command line arguments of the same name, missing ones are left as they are
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhysicsSettings {
+    pub gravity: Option<f32>,
+    pub time_scale: Option<f32>,
+    pub extended_precision: Option<bool>,
+    pub max_timestep_level: Option<u32>,
+    pub timestep_accuracy: Option<f32>,
+    pub discrete_collisions: Option<bool>,
+    pub fragmentation_speed: Option<f32>,
+    pub fragment_count: Option<u32>,
+    pub min_fragment_mass: Option<f32>,
+    pub ejecta_speed: Option<f32>,
+    pub max_particles: Option<u32>,
+}
+
+impl PhysicsSettings {
+    /// Checks the settings the command line arguments restrict to a range
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(gravity) = self.gravity.filter(|&gravity| gravity < 0.0) {
+            bail!("the gravity must not be negative but is {gravity}");
+        }
+        if let Some(level) = self
+            .max_timestep_level
+            .filter(|&level| level > MAX_TIMESTEP_LEVEL)
+        {
+            bail!("the max_timestep_level must be at most {MAX_TIMESTEP_LEVEL} but is {level}");
+        }
+        if let Some(accuracy) = self.timestep_accuracy.filter(|&accuracy| accuracy <= 0.0) {
+            bail!("the timestep_accuracy must be positive but is {accuracy}");
+        }
+        if let Some(count) = self
+            .fragment_count
+            .filter(|count| !(2..=MAX_FRAGMENTS).contains(count))
+        {
+            bail!("the fragment_count must be between 2 and {MAX_FRAGMENTS} but is {count}");
+        }
+        if self.max_particles == Some(0) {
+            bail!("the max_particles must be positive");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraSettings {
+    /// The point in the middle of the screen
+    pub center: Option<[f64; 2]>,
+    pub zoom: Option<f32>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Component {
+    /// Particles generated from a [`Preset`]
+    Generator(Generator),
+    /// A single particle
+    Body(Body),
+    /// Particles read from a file in the format of `example.csv`
+    Csv(CsvInclude),
+    /// Particles spawned on the bright pixels of an image
+    Image(ImageInclude),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Generator {
+    pub count: usize,
+    pub preset: Preset,
+    pub mass: Option<Distribution>,
+    pub radius: Option<Radius>,
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Body {
+    pub position: [f64; 2],
+    #[serde(default)]
+    pub velocity: [f32; 2],
+    pub mass: f32,
+    pub radius: f32,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvInclude {
+    /// Relative to the scene file
+    pub path: PathBuf,
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageInclude {
+    /// Relative to the scene file
+    pub path: PathBuf,
+    #[serde(default)]
+    pub generator: ImageGenerator,
+    pub mass: Option<Distribution>,
+    pub radius: Option<Radius>,
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+/// Scales and rotates particles around the origin, then moves them by `offset`
+/// and adds `velocity` to them
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transform {
+    pub offset: [f64; 2],
+    pub velocity: [f32; 2],
+    /// Counterclockwise in degrees
+    pub rotation: f32,
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 2],
+            velocity: [0.0; 2],
+            rotation: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn apply(&self, particles: &mut ParticleSet) {
+        particles.scale(self.scale as f64);
+        particles.rotate(self.rotation.to_radians());
+        particles.translate(DVec2::from_array(self.offset));
+        particles.boost(Vec2::from_array(self.velocity));
+    }
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+            .with_context(|| format!("Failed to parse `{}`", path.display()))
+    }
+
+    /// Parses a scene, CSV includes are resolved relative to `directory`
+    pub fn parse(text: &str, directory: &Path) -> anyhow::Result<Self> {
+        let file: SceneFile = toml::from_str(text)?;
+        let line = |span: std::ops::Range<usize>| text[..span.start].lines().count() + 1;
+
+        let physics = match file.physics {
+            Some(physics) => {
+                let line = line(physics.span());
+                physics
+                    .get_ref()
+                    .validate()
+                    .with_context(|| format!("physics at line {line}"))?;
+                physics.into_inner()
+            }
+            None => PhysicsSettings::default(),
+        };
+
+        let mut components = Vec::with_capacity(file.components.len());
+        let mut lines = Vec::with_capacity(file.components.len());
+        for (i, table) in file.components.into_iter().enumerate() {
+            let line = line(table.span());
+            let component = toml::Value::Table(table.into_inner())
+                .try_into()
+                .with_context(|| format!("component {} at line {line}", i + 1))?;
+            components.push(component);
+            lines.push(line);
+        }
+
+        Ok(Self {
+            physics,
+            camera: file.camera,
+            components,
+            emitters: file.emitters,
+            lines,
+            directory: directory.to_path_buf(),
+        })
+    }
+
+    /// Generates the particles of every component, `gravity` should already include
+    /// the physics settings of this scene
+    pub fn build(
+        &self,
+        gravity: Gravity,
+        properties: &ParticleProperties,
+    ) -> anyhow::Result<ParticleSet> {
+        let mut particles = ParticleSet::new();
+
+        for (i, (component, line)) in self.components.iter().zip(&self.lines).enumerate() {
+            let generated = self
+                .build_component(component, gravity, properties)
+                .and_then(|generated| {
+                    generated.validate()?;
+                    Ok(generated)
+                })
+                .with_context(|| format!("Failed to build component {} at line {line}", i + 1))?;
+            particles.merge(generated);
+        }
+
+        if particles.is_empty() {
+            bail!("Invalid scene: no components");
+        }
+
+        Ok(particles)
+    }
+
+    fn build_component(
+        &self,
+        component: &Component,
+        gravity: Gravity,
+        properties: &ParticleProperties,
+    ) -> anyhow::Result<ParticleSet> {
+        let mut particles = match component {
+            Component::Generator(generator) => {
+                let properties = ParticleProperties {
+                    mass: generator.mass.unwrap_or(properties.mass),
+                    radius: generator.radius.unwrap_or(properties.radius),
+                };
+
+                generator
+                    .preset
+                    .generate(generator.count, gravity, &properties)
+            }
+            Component::Body(body) => ParticleSet::from(vec![Particle::new(
+                DVec2::from_array(body.position),
+                Vec2::from_array(body.velocity),
+                body.radius,
+                body.mass,
+            )]),
+            Component::Csv(include) => csv::read_particles(&self.directory.join(&include.path))?,
+            Component::Image(include) => {
+                let properties = ParticleProperties {
+                    mass: include.mass.unwrap_or(properties.mass),
+                    radius: include.radius.unwrap_or(properties.radius),
+                };
+
+                include
+                    .generator
+                    .load(&self.directory.join(&include.path), &properties)?
+            }
+        };
+
+        if let Some(transform) = component.transform() {
+            transform.apply(&mut particles);
+        }
+
+        Ok(particles)
+    }
+}
+
+impl Component {
+    fn transform(&self) -> Option<&Transform> {
+        match self {
+            Component::Generator(generator) => Some(&generator.transform),
+            Component::Body(_) => None,
+            Component::Csv(include) => Some(&include.transform),
+            Component::Image(include) => Some(&include.transform),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAVITY: Gravity = Gravity {
+        gravitational_constant: 0.1,
+        delta_time: 1.0 / 60.0,
+    };
+
+    fn build(text: &str) -> anyhow::Result<ParticleSet> {
+        let properties = ParticleProperties {
+            mass: "constant:1".parse().unwrap(),
+            radius: "constant:0.1".parse().unwrap(),
+        };
+        Scene::parse(text, Path::new(env!("CARGO_MANIFEST_DIR")))?.build(GRAVITY, &properties)
+    }
+
+    #[test]
+    fn builds_components() {
+        let particles = build(
+            r#"
+            [[component]]
+            type = "generator"
+            count = 10
+            preset = { kind = "lattice", spacing = 1.0 }
+
+            [[component]]
+            type = "csv"
+            path = "example.csv"
+            transform = { offset = [100.0, 0.0] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(particles.len(), 15);
+        assert_eq!(particles[10].position(), DVec2::new(100.0, 0.0));
+    }
+
+    #[test]
+    fn errors_name_the_component() {
+        let err = build(
+            r#"
+            [[component]]
+            type = "body"
+            position = [0.0, 0.0]
+            mass = 1.0
+            radius = 1.0
+
+            [[component]]
+            type = "body"
+            position = [1.0, 0.0]
+            mass = -1.0
+            radius = 1.0
+            "#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to build component 2 at line 9: particle 1: the mass must be positive but is -1"
+        );
+
+        let err = build(
+            r#"
+            [[component]]
+            type = "generator"
+            count = 0
+            preset = { kind = "lattice", spacing = 1.0 }
+            "#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to build component 1 at line 3: no particles"
+        );
+    }
+
+    #[test]
+    fn physics_settings_are_checked() {
+        let error = |physics: &str| {
+            let text = format!("[camera]\nzoom = 1.0\n\n[physics]\n{physics}\n");
+            format!("{:#}", Scene::parse(&text, Path::new("")).err().unwrap())
+        };
+
+        assert!(Scene::parse(
+            "[physics]\ngravity = 0.0\nmax_timestep_level = 8",
+            Path::new("")
+        )
+        .is_ok());
+        assert_eq!(
+            error("gravity = -0.1"),
+            "physics at line 4: the gravity must not be negative but is -0.1"
+        );
+        assert_eq!(
+            error("max_timestep_level = 32"),
+            "physics at line 4: the max_timestep_level must be at most 8 but is 32"
+        );
+        assert_eq!(
+            error("timestep_accuracy = 0.0"),
+            "physics at line 4: the timestep_accuracy must be positive but is 0"
+        );
+        assert_eq!(
+            error("fragment_count = 1"),
+            "physics at line 4: the fragment_count must be between 2 and 8 but is 1"
+        );
+        assert_eq!(
+            error("max_particles = 0"),
+            "physics at line 4: the max_particles must be positive"
+        );
+    }
+}
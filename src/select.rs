@@ -0,0 +1,205 @@
+use std::borrow::Cow;
+
+use glam::Vec2;
+use wgpu::util::DeviceExt;
+
+use crate::physics::{PhysicsModule, DISPATCH_INDIRECT_OFFSET};
+
+/// What happens to the particles in a [`Selection`] box
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SelectMode {
+    /// Selects only the particles in the box
+    Replace = 0,
+    /// Adds the particles in the box to the selection
+    Add = 1,
+    /// Deselects every particle
+    Clear = 2,
+}
+
+/// A box in world space, see `select.wgsl`
+#[derive(Clone, Copy)]
+pub struct Selection {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub mode: SelectMode,
+}
+
+impl Selection {
+    /// The box spanned by two corners
+    pub fn new(a: Vec2, b: Vec2, mode: SelectMode) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+            mode,
+        }
+    }
+
+    pub fn clear() -> Self {
+        Self::new(Vec2::ZERO, Vec2::ZERO, SelectMode::Clear)
+    }
+}
+
+/// Marks particles as selected by setting the top bit of their id
+pub struct SelectModule {
+    selection_buffer: wgpu::Buffer,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_groups: [wgpu::BindGroup; 2],
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl SelectModule {
+    pub fn new(
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer; 2],
+        allocator_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let select_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("select.wgsl"))),
+        });
+
+        // [min_x, min_y, max_x, max_y, mode, padding]
+        let selection_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Selection Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; 8]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_groups = create_bind_groups(
+            device,
+            &bind_group_layout,
+            particle_buffers,
+            allocator_buffer,
+            &selection_buffer,
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Select Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &select_shader,
+            entry_point: "select",
+        });
+
+        Self {
+            selection_buffer,
+
+            bind_group_layout,
+            bind_groups,
+            pipeline,
+        }
+    }
+
+    pub fn resize_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer; 2],
+        allocator_buffer: &wgpu::Buffer,
+    ) {
+        self.bind_groups = create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            particle_buffers,
+            allocator_buffer,
+            &self.selection_buffer,
+        );
+    }
+
+    /// Applies `selection` to the current particle buffer, only one selection can be
+    /// encoded per submission
+    pub fn select(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        physics_module: &PhysicsModule,
+        selection: Selection,
+    ) {
+        let [min_x, min_y] = selection.min.to_array().map(f32::to_bits);
+        let [max_x, max_y] = selection.max.to_array().map(f32::to_bits);
+        let params = [min_x, min_y, max_x, max_y, selection.mode as u32];
+        queue.write_buffer(&self.selection_buffer, 0, bytemuck::cast_slice(&params));
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &self.bind_groups[physics_module.current], &[]);
+        cpass.dispatch_workgroups_indirect(
+            &physics_module.indirect_buffer,
+            DISPATCH_INDIRECT_OFFSET,
+        );
+    }
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    particle_buffers: &[wgpu::Buffer; 2],
+    allocator_buffer: &wgpu::Buffer,
+    selection_buffer: &wgpu::Buffer,
+) -> [wgpu::BindGroup; 2] {
+    particle_buffers.each_ref().map(|particle_buffer| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: allocator_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: selection_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    })
+}
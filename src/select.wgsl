@@ -0,0 +1,74 @@
+@group(0)
+@binding(0)
+var<storage, read_write> particles: array<Particle>;
+
+@group(0)
+@binding(1)
+var<storage, read> allocator: Allocator;
+
+@group(0)
+@binding(2)
+var<uniform> selection: Selection;
+
+// A box in world space and what to do with the particles in it
+struct Selection {
+    min: vec2<f32>,
+    max: vec2<f32>,
+    mode: u32,
+}
+
+struct Allocator {
+    capacity: u32,
+    count: u32,
+    alive: u32,
+    free_count: u32,
+    compacted: u32,
+    spawn_count: u32,
+    next_id: u32,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    radius: f32,
+    mass: f32,
+    // Low order part of `position`, only used with extended precision
+    position_lo: vec2<f32>,
+    level: u32,
+    time: u32,
+    color: u32,
+    // Index when uploaded, spawned particles count up from there and a shattered particle keeps
+    // it as its first fragment. The top bit marks selected particles and the one below
+    // the particles of the followed cluster
+    id: u32,
+}
+
+const SELECTED: u32 = 0x80000000u;
+
+// Selects only the particles in the box
+const MODE_REPLACE: u32 = 0;
+// Adds the particles in the box to the selection
+const MODE_ADD: u32 = 1;
+// Deselects every particle
+const MODE_CLEAR: u32 = 2;
+
+@compute
+@workgroup_size(256)
+fn select(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= allocator.count {
+        return;
+    }
+
+    let particle = particles[index];
+    let position = particle.position + particle.position_lo;
+    let inside = all(position >= selection.min) && all(position <= selection.max);
+
+    var id = particle.id;
+    if selection.mode == MODE_CLEAR || (selection.mode == MODE_REPLACE && !inside) {
+        id &= ~SELECTED;
+    } else if inside {
+        id |= SELECTED;
+    }
+    particles[index].id = id;
+}
@@ -0,0 +1,259 @@
+//! Appends the follow statistics to a CSV file to plot runs in other tools
+
+use std::{collections::VecDeque, fmt::Write as _, path::PathBuf, time::Instant};
+
+use anyhow::Context;
+use log::{error, info};
+use tokio::{io::AsyncWriteExt, runtime::Runtime, sync::mpsc, task::JoinHandle};
+
+use crate::follow::InfoOutput;
+
+/// Frames whose statistics can be in flight, see [`crate::readback::Readback`]
+const MAX_PENDING_FRAMES: usize = 8;
+
+/// The columns of a row, momentum, kinetic energy and angular momentum are totals. Gravity
+/// conserves the sum of the velocities and of `r × v` rather than the momentum and the
+/// angular momentum, see [`InfoOutput::specific_angular_momentum`].
+const HEADER: &str = "step,time,wall_time,particles,total_mass,\
+center_of_mass_x,center_of_mass_y,center_of_mass_velocity_x,center_of_mass_velocity_y,\
+centroid_x,centroid_y,avg_velocity_x,avg_velocity_y,min_x,min_y,max_x,max_y,\
+selection_x,selection_y,selection_mass,particle_x,particle_y,particle_mass,\
+heaviest_x,heaviest_y,heaviest_mass,cluster_x,cluster_y,cluster_mass,\
+percentile_radius,max_speed,momentum_x,momentum_y,kinetic_energy,angular_momentum,\
+velocity_sum_x,velocity_sum_y,specific_angular_momentum\n";
+
+/// Where the simulation was in a frame
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub step: u64,
+    /// Simulated seconds
+    pub time: f64,
+}
+
+struct Row {
+    sample: Sample,
+    /// Seconds since the log was created
+    wall_time: f64,
+    info: InfoOutput,
+}
+
+struct Writer {
+    path: String,
+    rows: mpsc::UnboundedSender<Row>,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+/// Logs a row every `interval` steps. The statistics of a frame arrive a frame or two late,
+/// so the step and time of every frame are kept until they do.
+pub struct StatsLog {
+    pub enabled: bool,
+    pub path: String,
+    /// Steps between rows
+    pub interval: u32,
+    /// Why the last log stopped
+    pub error: Option<String>,
+
+    start: Instant,
+    /// The frames whose statistics are still in flight with their samples and wall times
+    pending: VecDeque<(u64, Sample, f64)>,
+    /// Steps before this one are already logged
+    next_step: u64,
+    writer: Option<Writer>,
+}
+
+impl StatsLog {
+    /// Starts logging right away when `path` is given
+    pub fn new(path: Option<PathBuf>, interval: u32) -> Self {
+        Self {
+            enabled: path.is_some(),
+            path: path.map_or("stats.csv".into(), |path| path.display().to_string()),
+            interval,
+            error: None,
+
+            start: Instant::now(),
+            pending: VecDeque::new(),
+            next_step: 0,
+            writer: None,
+        }
+    }
+
+    /// Remembers where the simulation is in `frame`, has to be called for every frame
+    /// whose statistics are read back
+    pub fn record(&mut self, frame: u64, sample: Sample) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.pending.len() == MAX_PENDING_FRAMES {
+            self.pending.pop_front();
+        }
+        self.pending
+            .push_back((frame, sample, self.start.elapsed().as_secs_f64()));
+    }
+
+    /// Queues a row with the statistics of `frame` when `interval` steps passed since the last one,
+    /// the rows are written by a task on `runtime`
+    pub fn log(&mut self, runtime: &Runtime, frame: u64, info: &InfoOutput) {
+        self.check_writer(runtime);
+        if !self.enabled {
+            self.close(runtime);
+            self.pending.clear();
+            return;
+        }
+
+        while self
+            .pending
+            .front()
+            .is_some_and(|(pending, ..)| *pending < frame)
+        {
+            self.pending.pop_front();
+        }
+        let Some(&(pending, sample, wall_time)) = self.pending.front() else {
+            return;
+        };
+        if pending != frame {
+            return;
+        }
+        self.pending.pop_front();
+        if sample.step < self.next_step {
+            return;
+        }
+        self.next_step = sample.step + self.interval.max(1) as u64;
+
+        if self
+            .writer
+            .as_ref()
+            .is_some_and(|writer| writer.path != self.path)
+        {
+            self.close(runtime);
+        }
+        if self.writer.is_none() {
+            info!("Logging statistics to `{}`", self.path);
+            self.error = None;
+            let (rows, receiver) = mpsc::unbounded_channel();
+            self.writer = Some(Writer {
+                path: self.path.clone(),
+                rows,
+                task: runtime.spawn(write_rows(self.path.clone().into(), receiver)),
+            });
+        }
+
+        // A closed channel means the task failed, which the next call reports
+        let writer = self.writer.as_ref().unwrap();
+        let _ = writer.rows.send(Row {
+            sample,
+            wall_time,
+            info: *info,
+        });
+    }
+
+    /// Waits for the queued rows to be written and closes the file
+    pub fn close(&mut self, runtime: &Runtime) {
+        if let Some(writer) = self.writer.take() {
+            drop(writer.rows);
+            self.report(runtime.block_on(writer.task));
+        }
+    }
+
+    /// Stops logging when the task writing the rows failed
+    fn check_writer(&mut self, runtime: &Runtime) {
+        if self
+            .writer
+            .as_ref()
+            .is_some_and(|writer| writer.task.is_finished())
+        {
+            self.close(runtime);
+        }
+    }
+
+    fn report(&mut self, result: Result<anyhow::Result<()>, tokio::task::JoinError>) {
+        let err = match result {
+            Ok(Ok(())) => return,
+            Ok(Err(err)) => err,
+            Err(err) => anyhow::Error::new(err).context("The statistics log task failed"),
+        };
+        error!("{err:#}");
+        self.error = Some(format!("{err:#}"));
+        self.enabled = false;
+    }
+}
+
+/// Appends the rows to `path`, starting it with the header when it's empty
+async fn write_rows(path: PathBuf, mut rows: mpsc::UnboundedReceiver<Row>) -> anyhow::Result<()> {
+    let context = || format!("Failed to write `{}`", path.display());
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .with_context(context)?;
+
+    if file.metadata().await.with_context(context)?.len() == 0 {
+        file.write_all(HEADER.as_bytes())
+            .await
+            .with_context(context)?;
+    }
+
+    let mut text = String::new();
+    while let Some(row) = rows.recv().await {
+        text.clear();
+        row.write(&mut text)?;
+        file.write_all(text.as_bytes())
+            .await
+            .with_context(context)?;
+        // Lets other tools follow the file while it's written
+        file.flush().await.with_context(context)?;
+    }
+
+    Ok(())
+}
+
+impl Row {
+    /// Writes the row in the order of `HEADER`
+    fn write(&self, text: &mut String) -> std::fmt::Result {
+        let Row {
+            sample,
+            wall_time,
+            info,
+        } = self;
+        let momentum = info.center_of_mass_velocity * info.total_mass;
+        let velocity_sum = info.avg_velocity * info.count as f32;
+
+        write!(
+            text,
+            "{},{},{:.3},{},{},",
+            sample.step, sample.time, wall_time, info.count, info.total_mass,
+        )?;
+        for vector in [
+            info.center_of_mass,
+            info.center_of_mass_velocity,
+            info.centroid,
+            info.avg_velocity,
+            info.min_position,
+            info.max_position,
+        ] {
+            write!(text, "{},{},", vector.x, vector.y)?;
+        }
+        for (position, mass) in [
+            (info.selection_center, info.selection_mass),
+            (info.particle_position, info.particle_mass),
+            (info.heaviest_position, info.heaviest_mass),
+            (info.cluster_center, info.cluster_mass),
+        ] {
+            write!(text, "{},{},{},", position.x, position.y, mass)?;
+        }
+        writeln!(
+            text,
+            "{},{},{},{},{},{},{},{},{}",
+            info.percentile_radius,
+            info.max_speed,
+            momentum.x,
+            momentum.y,
+            info.kinetic_energy,
+            info.angular_momentum,
+            velocity_sum.x,
+            velocity_sum.y,
+            info.specific_angular_momentum,
+        )
+    }
+}
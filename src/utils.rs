@@ -9,6 +9,12 @@ pub fn multiple_of(mut value: u32, multiple: u32) -> u32 {
     value
 }
 
+/// Splits `value` into a double-single `(hi, lo)` pair where `hi + lo ≈ value`
+pub fn split_f64(value: f64) -> (f32, f32) {
+    let hi = value as f32;
+    (hi, (value - hi as f64) as f32)
+}
+
 /// A type thats assumed to exist when accessed
 pub enum Exists<T> {
     Some(T),